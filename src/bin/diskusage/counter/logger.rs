@@ -1,15 +1,19 @@
+use crate::filesystem::throttle::Throttle;
 use crate::models;
 use crate::models::definitions::DbModel;
 
 /// A function to log the disk usage statistics during runtime.
-/// Helps track the number of files and directories parsed per second.
+/// Helps track the number of files and directories parsed per second, and the load put on the
+/// filesystem by metadata operations.
 ///
 /// Arguments
 /// * `handle` - A tokio runtime handle to run the async functions.
 /// * `pool` - A sqlx database pool to query the database.
+/// * `throttle` - The throttle pacing the crawler's metadata operations.
 pub async fn logger_thread(
     handle: tokio::runtime::Handle,
     pool: std::sync::Arc<sqlx::Pool<sqlx::Postgres>>,
+    throttle: std::sync::Arc<Throttle>,
 ) {
     std::thread::spawn(move || {
         let log_frequency = match std::env::var("DISK_USAGE_LOG_FREQUENCY") {
@@ -29,6 +33,7 @@ pub async fn logger_thread(
         log::info!("Logging frequency: {:?}", log_frequency);
         let mut files_counter = 0;
         let mut directories_counter = 0;
+        let mut throttle_counter = throttle.snapshot();
         loop {
             let files_count = handle.block_on(async {
                 let result = models::definitions::File::count_all(&pool)
@@ -55,6 +60,32 @@ pub async fn logger_thread(
                 directories_count_diff as f64 / log_frequency.as_secs() as f64;
 
             log::info!("Parsed {} files ({} new) and {} directories ({} new): {:.2} files/s, {:.2} directories/s", files_count, files_count_diff, directories_count, directories_count_diff, files_per_second, directories_per_second);
+
+            let throttle_snapshot = throttle.snapshot();
+            let stats_diff = throttle_snapshot.stats - throttle_counter.stats;
+            let stat_time_diff = throttle_snapshot.stat_time - throttle_counter.stat_time;
+            throttle_counter = throttle_snapshot;
+
+            let stats_per_second = stats_diff as f64 / log_frequency.as_secs() as f64;
+            let mean_latency_ms = match stats_diff {
+                0 => 0.0,
+                _ => stat_time_diff.as_secs_f64() * 1000.0 / stats_diff as f64,
+            };
+            let config = throttle.config();
+            let limit = |limit: Option<String>| limit.unwrap_or_else(|| "none".to_string());
+            log::info!(
+                "Filesystem: {:.2} stats/s (limit {}), {:.3} ms mean stat latency, {} directory reads in flight (limit {}){}",
+                stats_per_second,
+                limit(config.max_stats_per_second.map(|rate| rate.to_string())),
+                mean_latency_ms,
+                throttle_snapshot.reads_in_flight,
+                limit(config.max_concurrent_reads.map(|reads| reads.to_string())),
+                match throttle_snapshot.adaptive_rate {
+                    Some(rate) => format!(", adaptive backoff to {:.2} stats/s", rate),
+                    None if config.adaptive => ", adaptive: not backing off".to_string(),
+                    None => String::new(),
+                }
+            );
             std::thread::sleep(log_frequency);
         }
     });
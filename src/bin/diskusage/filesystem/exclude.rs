@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+
+/// The name of the ignore file read from the root directory, using gitignore semantics.
+/// Files with this name in subdirectories are not read.
+pub const IGNORE_FILE_NAME: &str = ".diskusageignore";
+
+/// Rules deciding which subtrees the crawler skips.
+///
+/// Globs without a `/` match the name of an entry (so `.snapshot` skips every `.snapshot`
+/// directory), while globs containing a `/` match the full path. Regular expressions are
+/// matched against the full path. Ignore files follow gitignore semantics, with every
+/// pattern relative to the root of the scan, wherever the file itself lives.
+pub struct ExcludeRules {
+    name_globs: globset::GlobSet,
+    path_globs: globset::GlobSet,
+    regexes: Vec<regex::Regex>,
+    ignore: ignore::gitignore::Gitignore,
+    descriptions: Vec<String>,
+}
+
+impl ExcludeRules {
+    /// Build the exclusion rules for a scan.
+    ///
+    /// * `root` - The root directory of the scan. Its `.diskusageignore` file is read, if present.
+    /// * `globs` - Glob patterns to exclude.
+    /// * `regexes` - Regular expressions to exclude.
+    /// * `ignore_files` - Additional ignore files to read. Their patterns are relative to `root`.
+    ///
+    /// Returns the rules, or an error if a pattern or ignore file is invalid.
+    pub fn new(
+        root: &Path,
+        globs: &[String],
+        regexes: &[String],
+        ignore_files: &[PathBuf],
+    ) -> Result<ExcludeRules, Box<dyn std::error::Error>> {
+        let mut descriptions: Vec<String> = vec![];
+
+        let mut name_globs = globset::GlobSetBuilder::new();
+        let mut path_globs = globset::GlobSetBuilder::new();
+        for pattern in globs {
+            let glob = globset::GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()?;
+            if pattern.contains('/') {
+                path_globs.add(glob);
+            } else {
+                name_globs.add(glob);
+            }
+            descriptions.push(format!("glob:{}", pattern));
+        }
+
+        let mut compiled_regexes: Vec<regex::Regex> = vec![];
+        for pattern in regexes {
+            compiled_regexes.push(regex::Regex::new(pattern)?);
+            descriptions.push(format!("regex:{}", pattern));
+        }
+
+        let mut ignore = ignore::gitignore::GitignoreBuilder::new(root);
+        let mut ignore_paths: Vec<PathBuf> = ignore_files.to_vec();
+        let root_ignore_file = root.join(IGNORE_FILE_NAME);
+        if root_ignore_file.is_file() {
+            ignore_paths.push(root_ignore_file);
+        }
+        for path in ignore_paths {
+            if let Some(e) = ignore.add(&path) {
+                return Err(e.into());
+            }
+            let contents = std::fs::read_to_string(&path)?;
+            for line in contents.lines().map(str::trim) {
+                if !line.is_empty() && !line.starts_with('#') {
+                    descriptions.push(format!("ignore:{}:{}", path.display(), line));
+                }
+            }
+        }
+
+        Ok(ExcludeRules {
+            name_globs: name_globs.build()?,
+            path_globs: path_globs.build()?,
+            regexes: compiled_regexes,
+            ignore: ignore.build()?,
+            descriptions,
+        })
+    }
+
+    /// Check whether an entry, and everything below it, should be skipped.
+    ///
+    /// * `path` - The path to the entry.
+    /// * `is_dir` - Whether the entry is a directory.
+    ///
+    /// Returns
+    /// True if the entry matches any of the rules.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if let Some(name) = path.file_name() {
+            if self.name_globs.is_match(name) {
+                return true;
+            }
+        }
+        if self.path_globs.is_match(path) {
+            return true;
+        }
+        if !self.regexes.is_empty() {
+            let path_str = path.to_string_lossy();
+            if self.regexes.iter().any(|regex| regex.is_match(&path_str)) {
+                return true;
+            }
+        }
+        self.ignore.matched(path, is_dir).is_ignore()
+    }
+
+    /// A description of every rule, recorded with the scan.
+    pub fn descriptions(&self) -> &[String] {
+        &self.descriptions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir().join(format!(
+                "diskusage-exclude-{}-{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_names_anywhere() {
+        let rules =
+            ExcludeRules::new(Path::new("/data"), &strings(&[".snapshot"]), &[], &[]).unwrap();
+        assert!(rules.is_excluded(Path::new("/data/.snapshot"), true));
+        assert!(rules.is_excluded(Path::new("/data/a/b/.snapshot"), true));
+        assert!(!rules.is_excluded(Path::new("/data/.snapshots"), true));
+    }
+
+    #[test]
+    fn globs_with_a_slash_match_full_paths() {
+        let rules =
+            ExcludeRules::new(Path::new("/data"), &strings(&["/data/*/tmp"]), &[], &[]).unwrap();
+        assert!(rules.is_excluded(Path::new("/data/a/tmp"), true));
+        // `*` does not cross directory separators
+        assert!(!rules.is_excluded(Path::new("/data/a/b/tmp"), true));
+        assert!(!rules.is_excluded(Path::new("/data/tmp"), true));
+    }
+
+    #[test]
+    fn regexes_match_full_paths() {
+        let rules = ExcludeRules::new(
+            Path::new("/data"),
+            &[],
+            &strings(&[r"\.o$", "^/data/cache"]),
+            &[],
+        )
+        .unwrap();
+        assert!(rules.is_excluded(Path::new("/data/src/main.o"), false));
+        assert!(rules.is_excluded(Path::new("/data/cache"), true));
+        assert!(!rules.is_excluded(Path::new("/data/src/main.c"), false));
+        assert!(!rules.is_excluded(Path::new("/data/x/cache"), true));
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        assert!(ExcludeRules::new(Path::new("/data"), &strings(&["a[b"]), &[], &[]).is_err());
+        assert!(ExcludeRules::new(Path::new("/data"), &[], &strings(&["("]), &[]).is_err());
+    }
+
+    #[test]
+    fn root_ignore_file_uses_gitignore_semantics() {
+        let scratch = Scratch::new("root");
+        let root = scratch.0.clone();
+        std::fs::write(
+            root.join(IGNORE_FILE_NAME),
+            "# build output\ncache/\n*.tmp\n!keep.tmp\n",
+        )
+        .unwrap();
+
+        let rules = ExcludeRules::new(&root, &[], &[], &[]).unwrap();
+        // `cache/` only matches directories
+        assert!(rules.is_excluded(&root.join("a/cache"), true));
+        assert!(!rules.is_excluded(&root.join("a/cache"), false));
+        assert!(rules.is_excluded(&root.join("x.tmp"), false));
+        assert!(!rules.is_excluded(&root.join("keep.tmp"), false));
+        assert_eq!(
+            rules.descriptions().len(),
+            3,
+            "comments are not recorded: {:?}",
+            rules.descriptions()
+        );
+    }
+
+    #[test]
+    fn descriptions_list_every_rule() {
+        let rules = ExcludeRules::new(
+            Path::new("/data"),
+            &strings(&["*.bak"]),
+            &strings(&["^/data/tmp"]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(rules.descriptions(), ["glob:*.bak", "regex:^/data/tmp"]);
+    }
+
+    #[test]
+    fn ignore_files_outside_the_root_are_relative_to_the_root() {
+        let scratch = Scratch::new("outside");
+        let root = scratch.0.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let rules_file = scratch.0.join("rules");
+        std::fs::write(&rules_file, "/build\n*.log\n").unwrap();
+
+        let rules = ExcludeRules::new(&root, &[], &[], &[rules_file]).unwrap();
+        assert!(rules.is_excluded(&root.join("build"), true));
+        assert!(!rules.is_excluded(&root.join("src/build"), true));
+        assert!(rules.is_excluded(&root.join("src/debug.log"), false));
+    }
+}
@@ -1,63 +1,122 @@
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 
-/// Retrieves the metadata for the given file or directory.
-///
-/// * `path` - The path to the file or directory.
-///
-/// Returns
-/// The size of the file or directory in bytes.
-pub fn file_size(path: &Path) -> Result<u64, std::io::Error> {
-    let metadata = std::fs::metadata(path);
-    match metadata {
-        Ok(metadata) => Ok(metadata.len()),
-        Err(e) => {
-            log::error!("Error getting file size: {:?}", e);
-            Err(e)
-        }
-    }
+use super::throttle::Throttle;
+
+/// Every attribute the crawler records about a file or directory.
+/// All of them come from a single `stat`, so reading them costs one syscall per entry.
+/// On Linux the standard library reads metadata with `statx`, which also reports the birth time
+/// on filesystems that record it.
+#[derive(Debug, Clone)]
+pub struct Attributes {
+    /// The apparent size in bytes.
+    pub size: u64,
+    /// The number of bytes allocated on disk, as reported by `du`.
+    /// This differs from the apparent size for sparse files, and for small files
+    /// that are rounded up to a whole block.
+    pub allocated_size: u64,
+    /// The user ID of the owner.
+    pub owner: u32,
+    /// The group ID of the owning group.
+    pub group: u32,
+    /// The permission bits, including the setuid, setgid and sticky bits but not the file type.
+    pub mode: u32,
+    /// The last modified time, if it can be represented.
+    pub last_modified: Option<chrono::NaiveDateTime>,
+    /// The device number (`st_dev`) of the filesystem the entry lives on.
+    pub device: u64,
+    /// The inode number. Files sharing a device and inode number are hard links to the same data.
+    pub inode: u64,
+    /// The number of hard links.
+    pub nlink: u64,
+    /// The last access time, in nanoseconds since the Unix epoch.
+    pub atime_ns: Option<i64>,
+    /// The last modification time, in nanoseconds since the Unix epoch.
+    pub mtime_ns: Option<i64>,
+    /// The last status change time, in nanoseconds since the Unix epoch.
+    pub ctime_ns: Option<i64>,
+    /// The birth (creation) time, in nanoseconds since the Unix epoch.
+    /// None if the filesystem or kernel does not report it.
+    pub btime_ns: Option<i64>,
 }
 
-/// Get the owner of a file or directory.
+/// Get the attributes of a file or directory from its metadata.
 ///
-/// * `path` - The path to the file.
+/// * `metadata` - The metadata of the file or directory, such as the one returned by `DirEntry::metadata`.
 ///
 /// Returns
-/// The user ID of the owner of the file.
-pub fn owner(path: &Path) -> Option<u32> {
-    let metadata = std::fs::metadata(path);
-    match metadata {
-        Ok(metadata) => Some(metadata.uid()),
-        Err(_) => {
-            log::error!("Error getting owner of file: {:?}", path);
-            None
-        }
+/// The attributes recorded by the crawler.
+pub fn attributes(metadata: &std::fs::Metadata) -> Attributes {
+    Attributes {
+        size: metadata.len(),
+        // st_blocks is always counted in 512-byte units, regardless of the filesystem block size
+        allocated_size: metadata.blocks() * 512,
+        owner: metadata.uid(),
+        group: metadata.gid(),
+        mode: metadata.mode() & 0o7777,
+        last_modified: chrono::DateTime::from_timestamp(
+            metadata.mtime(),
+            metadata.mtime_nsec() as u32,
+        )
+        .map(|modified| modified.naive_utc()),
+        device: metadata.dev(),
+        inode: metadata.ino(),
+        nlink: metadata.nlink(),
+        atime_ns: nanoseconds(metadata.atime(), metadata.atime_nsec()),
+        mtime_ns: nanoseconds(metadata.mtime(), metadata.mtime_nsec()),
+        ctime_ns: nanoseconds(metadata.ctime(), metadata.ctime_nsec()),
+        btime_ns: metadata.created().ok().and_then(|created| {
+            match created.duration_since(std::time::UNIX_EPOCH) {
+                Ok(after) => i64::try_from(after.as_nanos()).ok(),
+                Err(e) => i64::try_from(e.duration().as_nanos())
+                    .ok()
+                    .map(|before| -before),
+            }
+        }),
     }
 }
 
-/// Get the last modified time of a file or directory.
+/// Combine a timestamp split into seconds and nanoseconds, as returned by `stat`,
+/// into nanoseconds since the Unix epoch. Returns None if it does not fit in an i64.
+fn nanoseconds(seconds: i64, nanoseconds: i64) -> Option<i64> {
+    seconds.checked_mul(1_000_000_000)?.checked_add(nanoseconds)
+}
+
+/// The attributes of a symbolic link itself, as opposed to the file it points to.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// The path the link points to, as stored in the link.
+    pub target: std::path::PathBuf,
+    /// Whether the target of the link does not exist.
+    pub dangling: bool,
+    /// The size of the link itself in bytes, as reported by lstat.
+    pub size: u64,
+    /// The user ID of the owner of the link.
+    pub owner: u32,
+}
+
+/// Get the attributes of a symbolic link, without following it.
 ///
-/// * `path` - The path to the file.
+/// * `path` - The path to the symbolic link.
+/// * `throttle` - Paces the metadata operations, one per syscall.
 ///
 /// Returns
-/// The last modified time of the file in chrono::DateTime<chrono::Utc>.
-pub fn last_modified(path: &Path) -> Result<chrono::NaiveDateTime, std::io::Error> {
-    let metadata = std::fs::metadata(path);
+/// The target of the link, whether it dangles, and the size and owner of the link itself.
+pub fn symlink(path: &Path, throttle: &Throttle) -> Result<Link, std::io::Error> {
+    let metadata = throttle.stat(|| std::fs::symlink_metadata(path));
     match metadata {
         Ok(metadata) => {
-            let modified = metadata.modified();
-            match modified {
-                Ok(modified) => {
-                    let modified = modified.duration_since(std::time::UNIX_EPOCH).unwrap();
-                    let modified = std::time::UNIX_EPOCH + modified;
-                    Ok(chrono::DateTime::<chrono::Utc>::from(modified).naive_utc())
-                }
-                Err(e) => {
-                    log::error!("Error getting modified time: {:?}", e);
-                    Err(e)
-                }
-            }
+            let target = throttle.stat(|| std::fs::read_link(path))?;
+            Ok(Link {
+                target,
+                dangling: throttle.stat(|| std::fs::metadata(path)).is_err(),
+                size: metadata.len(),
+                owner: metadata.uid(),
+            })
+        }
+        Err(e) => {
+            log::error!("Error getting symlink: {:?}", e);
+            Err(e)
         }
-        Err(e) => Err(e),
     }
 }
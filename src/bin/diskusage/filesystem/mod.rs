@@ -1 +1,6 @@
+pub mod exclude;
 pub mod fetch;
+pub mod mounts;
+pub mod symlinks;
+pub mod throttle;
+pub mod walker;
@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+
+/// A mounted filesystem, as listed in `/proc/self/mountinfo`.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    /// The device number of the filesystem, matching `st_dev` of the files on it.
+    pub device: u64,
+    /// The directory the filesystem is mounted on.
+    pub mount_point: PathBuf,
+    /// The filesystem type, such as `ext4` or `nfs4`.
+    pub fs_type: String,
+}
+
+/// The mount table of the machine running the crawler.
+#[derive(Debug, Clone, Default)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+/// Combine a major and minor device number into a device number, as glibc's `makedev` does.
+fn makedev(major: u64, minor: u64) -> u64 {
+    ((major & 0xfffff000) << 32)
+        | ((major & 0x00000fff) << 8)
+        | ((minor & 0xffffff00) << 12)
+        | (minor & 0x000000ff)
+}
+
+/// Decode the octal escapes (`\040` for a space, etc.) used for paths in `/proc/self/mountinfo`.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let digits = bytes.get(i + 1..i + 4).unwrap_or_default();
+        if bytes[i] == b'\\'
+            && digits.len() == 3
+            && digits.iter().all(|b| (b'0'..=b'7').contains(b))
+        {
+            let value = digits
+                .iter()
+                .fold(0u32, |value, b| value * 8 + (b - b'0') as u32);
+            decoded.push(value as u8);
+            i += 4;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Parse a single line of `/proc/self/mountinfo`.
+///
+/// The format is described in proc(5):
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_line(line: &str) -> Option<Mount> {
+    let (mount_fields, fs_fields) = line.split_once(" - ")?;
+    let mount_fields: Vec<&str> = mount_fields.split(' ').collect();
+    let (major, minor) = mount_fields.get(2)?.split_once(':')?;
+    let mount_point = mount_fields.get(4)?;
+    let fs_type = fs_fields.split(' ').next()?;
+
+    Some(Mount {
+        device: makedev(major.parse().ok()?, minor.parse().ok()?),
+        mount_point: PathBuf::from(unescape(mount_point)),
+        fs_type: fs_type.to_string(),
+    })
+}
+
+/// Find the mount with the longest mount point containing the path.
+fn longest_prefix<'a>(mounts: impl Iterator<Item = &'a Mount>, path: &Path) -> Option<&'a Mount> {
+    mounts
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.as_os_str().len())
+}
+
+impl MountTable {
+    /// Read the mount table from `/proc/self/mountinfo`.
+    ///
+    /// Returns the mount table, or an error if the file cannot be read.
+    pub fn read() -> Result<MountTable, std::io::Error> {
+        let contents = std::fs::read_to_string("/proc/self/mountinfo")?;
+        let mounts: Vec<Mount> = contents.lines().filter_map(parse_line).collect();
+        log::info!("Found {} mount points", mounts.len());
+        Ok(MountTable { mounts })
+    }
+
+    /// Find the mount a path lives on.
+    /// Mounts whose device matches the path's device are preferred. Among those (or among
+    /// all mounts, if none match), the one with the longest mount point containing the path wins.
+    ///
+    /// * `path` - The path to look up.
+    /// * `device` - The device number of the path, if known.
+    ///
+    /// Returns the mount, or None if no mount contains the path.
+    pub fn lookup(&self, path: &Path, device: Option<u64>) -> Option<&Mount> {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+        if let Some(device) = device {
+            let on_device = self.mounts.iter().filter(|mount| mount.device == device);
+            let found = longest_prefix(on_device, &path)
+                .or_else(|| self.mounts.iter().find(|mount| mount.device == device));
+            if found.is_some() {
+                return found;
+            }
+        }
+        longest_prefix(self.mounts.iter(), &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(device: u64, mount_point: &str) -> Mount {
+        Mount {
+            device,
+            mount_point: PathBuf::from(mount_point),
+            fs_type: "ext4".to_string(),
+        }
+    }
+
+    #[test]
+    fn unescape_decodes_octal_escapes() {
+        assert_eq!(unescape("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(
+            unescape("/mnt/tab\\011and\\134slash"),
+            "/mnt/tab\tand\\slash"
+        );
+    }
+
+    #[test]
+    fn unescape_keeps_incomplete_escapes() {
+        assert_eq!(unescape("/mnt/a\\04"), "/mnt/a\\04");
+        assert_eq!(unescape("/mnt/a\\089"), "/mnt/a\\089");
+        assert_eq!(unescape("/mnt/a\\"), "/mnt/a\\");
+    }
+
+    #[test]
+    fn parse_line_reads_device_mount_point_and_type() {
+        let parsed = parse_line(
+            "36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue",
+        )
+        .unwrap();
+        assert_eq!(parsed.device, makedev(98, 0));
+        assert_eq!(parsed.mount_point, PathBuf::from("/mnt2"));
+        assert_eq!(parsed.fs_type, "ext3");
+    }
+
+    #[test]
+    fn parse_line_handles_escapes_and_missing_optional_fields() {
+        let parsed =
+            parse_line("40 1 0:52 / /mnt/my\\040share rw - nfs4 server:/export rw").unwrap();
+        assert_eq!(parsed.mount_point, PathBuf::from("/mnt/my share"));
+        assert_eq!(parsed.fs_type, "nfs4");
+    }
+
+    #[test]
+    fn parse_line_rejects_malformed_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("36 35 98:0 /mnt1 /mnt2 rw").is_none());
+        assert!(parse_line("36 35 bad /mnt1 /mnt2 rw - ext3 /dev/root rw").is_none());
+    }
+
+    #[test]
+    fn makedev_matches_glibc() {
+        assert_eq!(makedev(8, 1), 0x801);
+        assert_eq!(makedev(259, 300), 0x11_032c);
+    }
+
+    #[test]
+    fn lookup_prefers_the_longest_mount_point() {
+        let table = MountTable {
+            mounts: vec![mount(1, "/"), mount(2, "/data"), mount(3, "/data/nfs")],
+        };
+        let found = |path: &str| table.lookup(Path::new(path), None).unwrap().device;
+        assert_eq!(found("/data/nfs/x"), 3);
+        assert_eq!(found("/data/nfsother"), 2);
+        assert_eq!(found("/home"), 1);
+    }
+
+    #[test]
+    fn lookup_prefers_mounts_on_the_same_device() {
+        // A bind mount of /data over /srv shares the device of /data
+        let table = MountTable {
+            mounts: vec![mount(1, "/"), mount(2, "/data"), mount(2, "/srv")],
+        };
+        assert_eq!(
+            table
+                .lookup(Path::new("/srv/x"), Some(2))
+                .unwrap()
+                .mount_point,
+            PathBuf::from("/srv")
+        );
+        // A path on a device whose mount point does not contain it still finds that mount
+        assert_eq!(
+            table
+                .lookup(Path::new("/other"), Some(2))
+                .unwrap()
+                .mount_point,
+            PathBuf::from("/data")
+        );
+    }
+}
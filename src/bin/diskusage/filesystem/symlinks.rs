@@ -0,0 +1,83 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::throttle::Throttle;
+
+/// Decides which symbolic links are followed when the crawler runs with `--follow-symlinks`.
+///
+/// A link is not followed when its target lies inside the scanned root, since the target
+/// is counted when the crawler reaches it directly. Targets outside the root are only
+/// crawled once: directories are identified by their device and inode numbers, so a
+/// directory reached through a second link (or nested inside an already followed target)
+/// is pruned. Loops through ancestor directories are detected by the walker itself.
+pub struct FollowPolicy {
+    root: PathBuf,
+    visited_files: dashmap::DashSet<PathBuf>,
+    visited_directories: dashmap::DashSet<(u64, u64)>,
+    throttle: Arc<Throttle>,
+}
+
+impl FollowPolicy {
+    /// Create a follow policy for the given root directory.
+    ///
+    /// * `root` - The root directory of the scan.
+    /// * `throttle` - Paces the lookups of link targets.
+    pub fn new(root: &Path, throttle: Arc<Throttle>) -> Result<FollowPolicy, std::io::Error> {
+        Ok(FollowPolicy {
+            root: throttle.stat(|| std::fs::canonicalize(root))?,
+            visited_files: dashmap::DashSet::new(),
+            visited_directories: dashmap::DashSet::new(),
+            throttle,
+        })
+    }
+
+    /// Check whether the crawler should follow a symbolic link.
+    ///
+    /// * `path` - The path to the symbolic link.
+    ///
+    /// Returns
+    /// True if the target should be crawled through this link.
+    pub fn should_follow(&self, path: &Path) -> bool {
+        let target = match self.throttle.stat(|| std::fs::canonicalize(path)) {
+            Ok(target) => target,
+            // Dangling links have nothing to follow
+            Err(_) => return false,
+        };
+        if target.starts_with(&self.root) {
+            log::debug!(
+                "Not following {:?}: target {:?} is inside the root",
+                path,
+                target
+            );
+            return false;
+        }
+        if self.throttle.stat(|| target.is_file()) && !self.visited_files.insert(target.clone()) {
+            log::debug!(
+                "Not following {:?}: target {:?} was already followed",
+                path,
+                target
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Check whether a directory is reached for the first time.
+    ///
+    /// * `entry` - The directory entry, with links already resolved.
+    ///
+    /// Returns
+    /// True if the directory has not been crawled yet, or if the entry is not a directory.
+    pub fn first_visit(&self, entry: &super::walker::DirEntry) -> bool {
+        if !entry.file_type().is_dir() {
+            return true;
+        }
+        match entry.metadata() {
+            Ok(metadata) => self
+                .visited_directories
+                .insert((metadata.dev(), metadata.ino())),
+            Err(_) => true,
+        }
+    }
+}
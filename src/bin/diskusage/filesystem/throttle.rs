@@ -0,0 +1,353 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Samples taken before the adaptive mode settles on a baseline latency.
+const WARMUP_SAMPLES: u64 = 64;
+/// Weight of the newest sample in the moving average of the stat latency.
+const SMOOTHING: f64 = 0.05;
+/// The average latency, relative to the baseline, above which the adaptive mode backs off.
+const BACKOFF_FACTOR: f64 = 2.0;
+/// The average latency, relative to the baseline, below which the adaptive mode speeds up again.
+const RECOVER_FACTOR: f64 = 1.25;
+/// Latencies below this are never backed off from, however much they rise relative to the
+/// baseline: on local or cached filesystems the jitter is larger than the latency itself.
+const MIN_BACKOFF_LATENCY: Duration = Duration::from_millis(1);
+/// How much the baseline may rise per adjustment, so that one unusually fast stretch does not
+/// set it for the rest of the crawl.
+const BASELINE_DRIFT: f64 = 0.01;
+/// How often the adaptive mode adjusts its pace.
+const ADJUST_EVERY: Duration = Duration::from_millis(100);
+/// The pace the adaptive mode starts backing off from.
+const MIN_ADAPTIVE_INTERVAL: Duration = Duration::from_micros(50);
+/// The slowest pace the adaptive mode backs off to, 10 stats per second.
+const MAX_ADAPTIVE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits on the load the crawler puts on the filesystem.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// The maximum number of metadata operations per second, across all threads.
+    pub max_stats_per_second: Option<u64>,
+    /// The maximum number of directories being read at the same time.
+    pub max_concurrent_reads: Option<usize>,
+    /// Slow down when the latency of metadata operations rises above its baseline.
+    pub adaptive: bool,
+}
+
+/// The state of the adaptive mode.
+#[derive(Debug, Default)]
+struct Adaptive {
+    samples: u64,
+    /// The moving average of the stat latency, in seconds.
+    average: f64,
+    /// The latency of an idle server: the lowest moving average seen once warmed up, slowly
+    /// drifting up.
+    baseline: Option<f64>,
+    /// The minimum time between two metadata operations, zero when not backing off.
+    interval: Duration,
+    adjusted_at: Option<Instant>,
+}
+
+impl Adaptive {
+    fn record(&mut self, latency: Duration) {
+        let latency = latency.as_secs_f64();
+        self.samples += 1;
+        self.average = if self.samples == 1 {
+            latency
+        } else {
+            self.average + SMOOTHING * (latency - self.average)
+        };
+        if self.samples < WARMUP_SAMPLES {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .adjusted_at
+            .is_some_and(|adjusted_at| now.duration_since(adjusted_at) < ADJUST_EVERY)
+        {
+            return;
+        }
+        self.adjusted_at = Some(now);
+
+        let baseline = self.baseline.map_or(self.average, |baseline| {
+            self.average.min(baseline * (1.0 + BASELINE_DRIFT))
+        });
+        self.baseline = Some(baseline);
+        let floor = MIN_BACKOFF_LATENCY.as_secs_f64();
+        if self.average > (baseline * BACKOFF_FACTOR).max(floor) {
+            self.interval = (self.interval * 2)
+                .max(MIN_ADAPTIVE_INTERVAL)
+                .min(MAX_ADAPTIVE_INTERVAL);
+        } else if self.average < (baseline * RECOVER_FACTOR).max(floor) {
+            self.interval /= 2;
+            if self.interval < MIN_ADAPTIVE_INTERVAL {
+                self.interval = Duration::ZERO;
+            }
+        }
+    }
+}
+
+/// A snapshot of the throttle's counters, for the periodic logger.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Snapshot {
+    /// The number of metadata operations so far.
+    pub stats: u64,
+    /// The total time spent in metadata operations so far.
+    pub stat_time: Duration,
+    /// The number of directories being read right now.
+    pub reads_in_flight: usize,
+    /// The pace imposed by the adaptive mode, in stats per second, if it is backing off.
+    pub adaptive_rate: Option<f64>,
+}
+
+/// Paces the crawler's metadata operations and bounds its concurrent directory reads, so that
+/// a crawl does not saturate the metadata server of a shared filesystem.
+///
+/// Every thread goes through the same throttle: the rate limit is global, not per thread.
+/// Without limits it only counts operations.
+#[derive(Debug)]
+pub struct Throttle {
+    config: Config,
+    next_slot: Mutex<Instant>,
+    reads: Mutex<usize>,
+    read_finished: Condvar,
+    adaptive: Mutex<Adaptive>,
+    stats: AtomicU64,
+    stat_nanos: AtomicU64,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle::new(Config::default())
+    }
+}
+
+/// A directory read in progress. The slot is given back when it is dropped.
+pub struct ReadPermit<'t> {
+    throttle: &'t Throttle,
+}
+
+impl Drop for ReadPermit<'_> {
+    fn drop(&mut self) {
+        let mut reads = self.throttle.reads.lock().unwrap();
+        *reads -= 1;
+        self.throttle.read_finished.notify_one();
+    }
+}
+
+impl Throttle {
+    /// Create a throttle with the given limits.
+    pub fn new(config: Config) -> Throttle {
+        Throttle {
+            config,
+            next_slot: Mutex::new(Instant::now()),
+            reads: Mutex::new(0),
+            read_finished: Condvar::new(),
+            adaptive: Mutex::new(Adaptive::default()),
+            stats: AtomicU64::new(0),
+            stat_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// The limits of the throttle.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Describe the limits in effect, for logging.
+    pub fn describe(&self) -> String {
+        let mut limits = Vec::new();
+        if let Some(rate) = self.config.max_stats_per_second {
+            limits.push(format!("at most {} stats/s", rate));
+        }
+        if let Some(reads) = self.config.max_concurrent_reads {
+            limits.push(format!("at most {} concurrent directory reads", reads));
+        }
+        if self.config.adaptive {
+            limits.push("backing off when stat latency rises".to_string());
+        }
+        if limits.is_empty() {
+            "unlimited".to_string()
+        } else {
+            limits.join(", ")
+        }
+    }
+
+    /// Run a metadata operation, such as `stat` or `readlink`, once the rate limit allows it.
+    ///
+    /// Arguments
+    /// * `operation` - The operation to run.
+    ///
+    /// Returns
+    /// The result of the operation.
+    pub fn stat<T>(&self, operation: impl FnOnce() -> T) -> T {
+        self.pace();
+        let started = Instant::now();
+        let result = operation();
+        let latency = started.elapsed();
+
+        self.stats.fetch_add(1, Ordering::Relaxed);
+        self.stat_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+        if self.config.adaptive {
+            self.adaptive.lock().unwrap().record(latency);
+        }
+        result
+    }
+
+    /// Wait for a free directory read slot, if their number is limited.
+    ///
+    /// Returns
+    /// The slot, held until the directory has been read.
+    pub fn read_dir(&self) -> ReadPermit<'_> {
+        let mut reads = self.reads.lock().unwrap();
+        if let Some(max_reads) = self.config.max_concurrent_reads {
+            while *reads >= max_reads.max(1) {
+                reads = self.read_finished.wait(reads).unwrap();
+            }
+        }
+        *reads += 1;
+        ReadPermit { throttle: self }
+    }
+
+    /// A snapshot of the counters.
+    pub fn snapshot(&self) -> Snapshot {
+        let adaptive_interval = self.adaptive.lock().unwrap().interval;
+        Snapshot {
+            stats: self.stats.load(Ordering::Relaxed),
+            stat_time: Duration::from_nanos(self.stat_nanos.load(Ordering::Relaxed)),
+            reads_in_flight: *self.reads.lock().unwrap(),
+            adaptive_rate: (!adaptive_interval.is_zero())
+                .then(|| 1.0 / adaptive_interval.as_secs_f64()),
+        }
+    }
+
+    /// Sleep until the next metadata operation may start.
+    /// Slots are handed out in order, one interval apart, so the rate holds across threads.
+    fn pace(&self) {
+        let configured = self
+            .config
+            .max_stats_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs_f64(1.0 / rate as f64))
+            .unwrap_or_default();
+        let adaptive = if self.config.adaptive {
+            self.adaptive.lock().unwrap().interval
+        } else {
+            Duration::ZERO
+        };
+        let interval = configured.max(adaptive);
+        if interval.is_zero() {
+            return;
+        }
+
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record a sample as if the last adjustment was long enough ago for this one to adjust.
+    fn record_adjusting(adaptive: &mut Adaptive, latency: Duration) {
+        adaptive.adjusted_at = None;
+        adaptive.record(latency);
+    }
+
+    /// An adaptive controller warmed up on a steady latency.
+    fn warmed_up(latency: Duration) -> Adaptive {
+        let mut adaptive = Adaptive::default();
+        for _ in 0..WARMUP_SAMPLES {
+            adaptive.record(latency);
+        }
+        adaptive
+    }
+
+    #[test]
+    fn nothing_is_adjusted_during_warmup() {
+        let mut adaptive = Adaptive::default();
+        for _ in 0..WARMUP_SAMPLES - 1 {
+            adaptive.record(Duration::from_millis(50));
+        }
+        assert_eq!(adaptive.baseline, None);
+        assert_eq!(adaptive.interval, Duration::ZERO);
+    }
+
+    #[test]
+    fn warmup_sets_the_baseline() {
+        let adaptive = warmed_up(Duration::from_millis(2));
+        let baseline = adaptive.baseline.unwrap();
+        assert!((baseline - 0.002).abs() < 1e-9, "{}", baseline);
+        assert_eq!(adaptive.interval, Duration::ZERO);
+    }
+
+    #[test]
+    fn rising_latency_backs_off_up_to_the_slowest_pace() {
+        let mut adaptive = warmed_up(Duration::from_millis(2));
+        let mut intervals = vec![];
+        for _ in 0..200 {
+            record_adjusting(&mut adaptive, Duration::from_millis(20));
+            intervals.push(adaptive.interval);
+        }
+        assert!(intervals.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(intervals.contains(&MIN_ADAPTIVE_INTERVAL));
+        assert_eq!(adaptive.interval, MAX_ADAPTIVE_INTERVAL);
+    }
+
+    #[test]
+    fn falling_latency_recovers_full_speed() {
+        let mut adaptive = warmed_up(Duration::from_millis(2));
+        for _ in 0..200 {
+            record_adjusting(&mut adaptive, Duration::from_millis(20));
+        }
+        for _ in 0..200 {
+            record_adjusting(&mut adaptive, Duration::from_millis(2));
+        }
+        assert_eq!(adaptive.interval, Duration::ZERO);
+    }
+
+    #[test]
+    fn latencies_below_the_floor_never_back_off() {
+        let mut adaptive = warmed_up(Duration::from_micros(10));
+        for _ in 0..200 {
+            record_adjusting(&mut adaptive, Duration::from_micros(500));
+        }
+        assert_eq!(adaptive.interval, Duration::ZERO);
+    }
+
+    #[test]
+    fn adjustments_are_spaced_out() {
+        let mut adaptive = warmed_up(Duration::from_millis(2));
+        adaptive.average = 0.02;
+        record_adjusting(&mut adaptive, Duration::from_millis(20));
+        let interval = adaptive.interval;
+        assert_eq!(interval, MIN_ADAPTIVE_INTERVAL);
+        // Within ADJUST_EVERY of the last adjustment, samples only move the average
+        for _ in 0..10 {
+            adaptive.record(Duration::from_millis(20));
+        }
+        assert_eq!(adaptive.interval, interval);
+    }
+
+    #[test]
+    fn snapshot_reports_the_adaptive_rate() {
+        let throttle = Throttle::new(Config {
+            adaptive: true,
+            ..Config::default()
+        });
+        assert_eq!(throttle.snapshot().adaptive_rate, None);
+        throttle.adaptive.lock().unwrap().interval = Duration::from_millis(10);
+        let rate = throttle.snapshot().adaptive_rate.unwrap();
+        assert!((rate - 100.0).abs() < 1e-6, "{}", rate);
+    }
+}
@@ -0,0 +1,682 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::throttle::Throttle;
+
+/// An entry found while walking a directory tree.
+///
+/// When links are followed, the file type and metadata describe the target of a link,
+/// while `path_is_symlink` still tells whether the entry itself is a link.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: PathBuf,
+    file_type: std::fs::FileType,
+    depth: usize,
+    path_is_symlink: bool,
+    follow_link: bool,
+    metadata: Option<std::fs::Metadata>,
+    throttle: Arc<Throttle>,
+}
+
+impl DirEntry {
+    /// The full path of the entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file type of the entry, or of its target if the link was followed.
+    pub fn file_type(&self) -> std::fs::FileType {
+        self.file_type
+    }
+
+    /// The depth of the entry below the root, which has depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Whether the entry itself is a symbolic link.
+    pub fn path_is_symlink(&self) -> bool {
+        self.path_is_symlink
+    }
+
+    /// The metadata of the entry, or of its target if the link was followed.
+    /// Metadata fetched while walking is reused, otherwise it is read from the filesystem,
+    /// subject to the walker's throttle.
+    pub fn metadata(&self) -> Result<std::fs::Metadata, std::io::Error> {
+        match &self.metadata {
+            Some(metadata) => Ok(metadata.clone()),
+            None if self.follow_link => self.throttle.stat(|| std::fs::metadata(&self.path)),
+            None => self.throttle.stat(|| std::fs::symlink_metadata(&self.path)),
+        }
+    }
+
+    /// The device and inode numbers identifying the entry, or its target if the link was followed.
+    /// Only known when the metadata was fetched while walking, so this never reads the filesystem.
+    fn identity(&self) -> Option<(u64, u64)> {
+        self.metadata
+            .as_ref()
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+    }
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    Io {
+        path: Option<PathBuf>,
+        error: std::io::Error,
+    },
+    Loop {
+        ancestor: PathBuf,
+        child: PathBuf,
+    },
+}
+
+/// An error found while walking a directory tree, such as an unreadable directory or a symlink loop.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+impl Error {
+    fn io(path: &Path, error: std::io::Error) -> Error {
+        Error {
+            kind: ErrorKind::Io {
+                path: Some(path.to_path_buf()),
+                error,
+            },
+        }
+    }
+
+    /// The path the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.kind {
+            ErrorKind::Io { path, .. } => path.as_deref(),
+            ErrorKind::Loop { child, .. } => Some(child),
+        }
+    }
+
+    /// The ancestor directory that a followed link loops back to, if this is a loop.
+    pub fn loop_ancestor(&self) -> Option<&Path> {
+        match &self.kind {
+            ErrorKind::Loop { ancestor, .. } => Some(ancestor),
+            ErrorKind::Io { .. } => None,
+        }
+    }
+
+    /// The underlying I/O error, if any.
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        match &self.kind {
+            ErrorKind::Io { error, .. } => Some(error),
+            ErrorKind::Loop { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ErrorKind::Io {
+                path: Some(path),
+                error,
+            } => write!(f, "IO error for operation on {}: {}", path.display(), error),
+            ErrorKind::Io { path: None, error } => error.fmt(f),
+            ErrorKind::Loop { ancestor, child } => write!(
+                f,
+                "File system loop found: {} points to an ancestor {}",
+                child.display(),
+                ancestor.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// An entry of a directory that could not be read.
+struct EntryError {
+    /// The path of the entry, if it is known.
+    path: Option<PathBuf>,
+    error: std::io::Error,
+    /// Whether the error came from resolving a followed link, rather than from the directory.
+    followed_link: bool,
+}
+
+/// A directory whose subtree is still being walked.
+struct Pending {
+    path: PathBuf,
+    /// One for the directory's own read, plus one for every subdirectory still being walked.
+    remaining: AtomicUsize,
+    /// Cleared when part of the subtree was not read, such as below the maximum depth.
+    complete: AtomicBool,
+    parent: Option<Arc<Pending>>,
+}
+
+impl Pending {
+    /// Mark part of the subtree as not read. The directory and its ancestors are reported as
+    /// incomplete when they finish.
+    fn mark_incomplete(&self) {
+        self.complete.store(false, Ordering::Release);
+    }
+
+    /// Mark one unit of work on the directory as done. When the last one is done, the whole
+    /// subtree has been walked: report it, and release the parent in turn.
+    fn release<D: Fn(&Path, bool)>(self: Arc<Self>, finish_directory: &D) {
+        let mut pending = self;
+        while pending.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let complete = pending.complete.load(Ordering::Acquire);
+            finish_directory(&pending.path, complete);
+            match &pending.parent {
+                Some(parent) => {
+                    if !complete {
+                        parent.mark_incomplete();
+                    }
+                    pending = Arc::clone(parent);
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// The state shared by every task of a walk.
+struct Context<'s, P, F, D> {
+    root_device: u64,
+    filter_entry: &'s P,
+    visit: &'s F,
+    finish_directory: &'s D,
+}
+
+/// A parallel directory walker.
+///
+/// Every directory is read in its own rayon task, so reads of sibling directories proceed
+/// concurrently and idle threads steal pending directories from busy ones. This matters on
+/// high latency filesystems such as NFS, where a single threaded walk spends most of its time
+/// waiting on `readdir` and `stat`. The walk uses the current rayon thread pool; run it inside
+/// `ThreadPool::install` to control the number of threads.
+///
+/// A directory is always visited before any of its contents are read, so records derived from
+/// a directory are produced before those of its children. Once every entry below a directory
+/// has been visited, the directory is reported as finished, always before its parent.
+pub struct Walker {
+    root: PathBuf,
+    follow_links: bool,
+    same_file_system: bool,
+    max_depth: usize,
+    stop: Arc<AtomicBool>,
+    throttle: Arc<Throttle>,
+}
+
+impl Walker {
+    /// Create a walker for the given root directory.
+    /// By default links are not followed, filesystem boundaries are crossed and depth is unlimited.
+    pub fn new<P: AsRef<Path>>(root: P) -> Walker {
+        Walker {
+            root: root.as_ref().to_path_buf(),
+            follow_links: false,
+            same_file_system: false,
+            max_depth: usize::MAX,
+            stop: Arc::new(AtomicBool::new(false)),
+            throttle: Arc::new(Throttle::default()),
+        }
+    }
+
+    /// Follow symbolic links. Links that loop back to an ancestor directory are reported as errors.
+    pub fn follow_links(mut self, follow_links: bool) -> Walker {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Do not descend into directories on a different filesystem than the root.
+    pub fn same_file_system(mut self, same_file_system: bool) -> Walker {
+        self.same_file_system = same_file_system;
+        self
+    }
+
+    /// Do not visit entries deeper than this below the root.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Walker {
+        self.max_depth = max_depth.unwrap_or(usize::MAX);
+        self
+    }
+
+    /// Stop the walk once the flag is set. Directories already being read are abandoned, no new
+    /// ones are read, and directories whose subtree was cut short are never reported as finished.
+    pub fn stop_flag(mut self, stop: Arc<AtomicBool>) -> Walker {
+        self.stop = stop;
+        self
+    }
+
+    /// Pace metadata operations and directory reads with the given throttle, which is also
+    /// used by the entries' `metadata`.
+    pub fn throttle(mut self, throttle: Arc<Throttle>) -> Walker {
+        self.throttle = throttle;
+        self
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Walk the tree, blocking until every entry has been visited or the walk is stopped.
+    ///
+    /// Arguments
+    /// * `filter_entry` - Called for every entry below the root. Entries for which it returns
+    ///   false are skipped, and directories are not descended into.
+    /// * `visit` - Called for every entry and error, from any of the pool's threads.
+    /// * `finish_directory` - Called with the path of every visited directory, once its whole
+    ///   subtree has been visited, and whether all of it was read. Directories at the maximum
+    ///   depth or with read errors, and their ancestors, are reported as incomplete.
+    pub fn run<P, F, D>(&self, filter_entry: P, visit: F, finish_directory: D)
+    where
+        P: Fn(&DirEntry) -> bool + Sync,
+        F: Fn(Result<DirEntry, Error>) + Sync,
+        D: Fn(&Path, bool) + Sync,
+    {
+        let metadata = match self.throttle.stat(|| std::fs::metadata(&self.root)) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                visit(Err(Error::io(&self.root, e)));
+                return;
+            }
+        };
+        let root = DirEntry {
+            path: self.root.clone(),
+            file_type: metadata.file_type(),
+            depth: 0,
+            path_is_symlink: self
+                .throttle
+                .stat(|| std::fs::symlink_metadata(&self.root))
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false),
+            follow_link: true,
+            metadata: Some(metadata.clone()),
+            throttle: Arc::clone(&self.throttle),
+        };
+        if !root.file_type.is_dir() {
+            visit(Ok(root));
+            return;
+        }
+        let context = Context {
+            root_device: metadata.dev(),
+            filter_entry: &filter_entry,
+            visit: &visit,
+            finish_directory: &finish_directory,
+        };
+        let ancestors = vec![(root.path.clone(), (metadata.dev(), metadata.ino()))];
+        rayon::scope(|scope| self.walk_directory(scope, &context, root, None, ancestors));
+    }
+
+    /// Visit a directory, then read it and visit its contents.
+    /// Subdirectories are spawned as new tasks on the scope.
+    fn walk_directory<'s, P, F, D>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        context: &'s Context<'s, P, F, D>,
+        directory: DirEntry,
+        parent: Option<Arc<Pending>>,
+        ancestors: Vec<(PathBuf, (u64, u64))>,
+    ) where
+        P: Fn(&DirEntry) -> bool + Sync,
+        F: Fn(Result<DirEntry, Error>) + Sync,
+        D: Fn(&Path, bool) + Sync,
+    {
+        // The parent is never released, so neither it nor its ancestors are reported as finished
+        if self.stopped() {
+            return;
+        }
+        let (filter_entry, visit) = (context.filter_entry, context.visit);
+        let path = directory.path.clone();
+        let depth = directory.depth;
+        visit(Ok(directory));
+        let pending = Arc::new(Pending {
+            path: path.clone(),
+            remaining: AtomicUsize::new(1),
+            complete: AtomicBool::new(true),
+            parent,
+        });
+        if depth >= self.max_depth {
+            pending.mark_incomplete();
+            pending.release(context.finish_directory);
+            return;
+        }
+
+        // The slot is held until every entry of the directory has been visited
+        let _permit = self.throttle.read_dir();
+        let read_dir = match std::fs::read_dir(&path) {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                visit(Err(Error::io(&path, e)));
+                pending.mark_incomplete();
+                pending.release(context.finish_directory);
+                return;
+            }
+        };
+        for dir_entry in read_dir {
+            if self.stopped() {
+                return;
+            }
+            let entry = match dir_entry
+                .map_err(|error| EntryError {
+                    path: None,
+                    error,
+                    followed_link: false,
+                })
+                .and_then(|dir_entry| self.entry(dir_entry, depth + 1))
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // A link whose target cannot be read is recorded as a link, nothing is missing
+                    if !e.followed_link {
+                        pending.mark_incomplete();
+                    }
+                    visit(Err(Error::io(e.path.as_deref().unwrap_or(&path), e.error)));
+                    continue;
+                }
+            };
+            if !entry.file_type.is_dir() {
+                if filter_entry(&entry) {
+                    visit(Ok(entry));
+                }
+                continue;
+            }
+
+            // Directories are only stat'ed up front when links are followed or filesystems checked
+            let identity = entry.identity();
+            if self.same_file_system && identity.is_some_and(|(dev, _)| dev != context.root_device)
+            {
+                continue;
+            }
+            if self.follow_links && entry.path_is_symlink {
+                if let Some((ancestor, _)) = ancestors
+                    .iter()
+                    .find(|(_, ancestor)| Some(*ancestor) == identity)
+                {
+                    visit(Err(Error {
+                        kind: ErrorKind::Loop {
+                            ancestor: ancestor.clone(),
+                            child: entry.path.clone(),
+                        },
+                    }));
+                    continue;
+                }
+            }
+            if !filter_entry(&entry) {
+                continue;
+            }
+
+            // Ancestors are only compared to detect loops through followed links
+            let mut ancestors = ancestors.clone();
+            if let Some(identity) = identity.filter(|_| self.follow_links) {
+                ancestors.push((entry.path.clone(), identity));
+            }
+            pending.remaining.fetch_add(1, Ordering::AcqRel);
+            let parent = Some(Arc::clone(&pending));
+            scope.spawn(move |scope| self.walk_directory(scope, context, entry, parent, ancestors));
+        }
+        pending.release(context.finish_directory);
+    }
+
+    /// Build an entry from a `readdir` result, resolving links when they are followed.
+    /// Only links and, when needed for loop or filesystem checks, directories are stat'ed here.
+    fn entry(&self, dir_entry: std::fs::DirEntry, depth: usize) -> Result<DirEntry, EntryError> {
+        let path = dir_entry.path();
+        let file_type = match dir_entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(error) => {
+                return Err(EntryError {
+                    path: Some(path),
+                    error,
+                    followed_link: false,
+                })
+            }
+        };
+        let path_is_symlink = file_type.is_symlink();
+        let follow_link = self.follow_links && path_is_symlink;
+
+        let metadata = if follow_link {
+            match self.throttle.stat(|| std::fs::metadata(&path)) {
+                Ok(metadata) => Some(metadata),
+                Err(error) => {
+                    return Err(EntryError {
+                        path: Some(path),
+                        error,
+                        followed_link: true,
+                    })
+                }
+            }
+        } else if file_type.is_dir() && (self.follow_links || self.same_file_system) {
+            self.throttle.stat(|| std::fs::symlink_metadata(&path)).ok()
+        } else {
+            None
+        };
+
+        Ok(DirEntry {
+            file_type: metadata
+                .as_ref()
+                .map(|metadata| metadata.file_type())
+                .unwrap_or(file_type),
+            path,
+            depth,
+            path_is_symlink,
+            follow_link,
+            metadata,
+            throttle: Arc::clone(&self.throttle),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A scratch directory, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir().join(format!(
+                "diskusage-walker-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        /// Create the directories and empty files below the scratch directory.
+        /// Paths ending in `/` are directories.
+        fn with(self, paths: &[&str]) -> Scratch {
+            for path in paths {
+                let full = self.0.join(path);
+                if path.ends_with('/') {
+                    std::fs::create_dir_all(&full).unwrap();
+                } else {
+                    std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+                    std::fs::write(&full, b"").unwrap();
+                }
+            }
+            self
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Visit(PathBuf),
+        Error(Option<PathBuf>),
+        Loop(PathBuf),
+        Finish(PathBuf, bool),
+    }
+
+    /// Walk a tree and record every callback, in the order they were made.
+    /// `on_visit` is called for every visited entry before it is recorded.
+    fn walk(walker: Walker, on_visit: impl Fn(&DirEntry) + Sync) -> Vec<Event> {
+        let events = Mutex::new(Vec::new());
+        walker.run(
+            |_| true,
+            |entry| {
+                let event = match entry {
+                    Ok(entry) => {
+                        on_visit(&entry);
+                        Event::Visit(entry.path().to_path_buf())
+                    }
+                    Err(e) => match e.loop_ancestor() {
+                        Some(ancestor) => Event::Loop(ancestor.to_path_buf()),
+                        None => Event::Error(e.path().map(Path::to_path_buf)),
+                    },
+                };
+                events.lock().unwrap().push(event);
+            },
+            |path, complete| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(Event::Finish(path.to_path_buf(), complete))
+            },
+        );
+        events.into_inner().unwrap()
+    }
+
+    fn position(events: &[Event], event: &Event) -> usize {
+        events
+            .iter()
+            .position(|e| e == event)
+            .unwrap_or_else(|| panic!("{:?} not found in {:?}", event, events))
+    }
+
+    fn finished(events: &[Event], path: &Path) -> bool {
+        events
+            .iter()
+            .any(|e| matches!(e, Event::Finish(p, _) if p == path))
+    }
+
+    fn complete(events: &[Event], path: &Path) -> bool {
+        events
+            .iter()
+            .find_map(|e| match e {
+                Event::Finish(p, complete) if p == path => Some(*complete),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("{} not finished in {:?}", path.display(), events))
+    }
+
+    #[test]
+    fn directories_are_visited_before_their_contents() {
+        let scratch = Scratch::new("order").with(&["a/b/c/file", "a/d/file", "e/file"]);
+        let events = walk(Walker::new(&scratch.0), |_| {});
+
+        for event in &events {
+            let Event::Visit(path) = event else { continue };
+            if path == &scratch.0 {
+                continue;
+            }
+            let parent = Event::Visit(path.parent().unwrap().to_path_buf());
+            assert!(position(&events, &parent) < position(&events, event));
+        }
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, Event::Visit(_)))
+                .count(),
+            9
+        );
+    }
+
+    #[test]
+    fn directories_finish_after_their_whole_subtree() {
+        let scratch = Scratch::new("finish").with(&["a/b/c/file", "a/d/file", "e/"]);
+        let events = walk(Walker::new(&scratch.0), |_| {});
+
+        for (i, event) in events.iter().enumerate() {
+            let path = match event {
+                Event::Visit(path) | Event::Finish(path, _) => path,
+                _ => panic!("unexpected {:?}", event),
+            };
+            for ancestor in path.ancestors().skip(1) {
+                if ancestor.starts_with(&scratch.0) {
+                    let finish = Event::Finish(ancestor.to_path_buf(), true);
+                    assert!(position(&events, &finish) > i, "{:?}", events);
+                }
+            }
+        }
+        assert_eq!(events.last(), Some(&Event::Finish(scratch.0.clone(), true)));
+    }
+
+    #[test]
+    fn directories_below_the_maximum_depth_are_incomplete() {
+        let scratch = Scratch::new("depth").with(&["a/b/c/file", "d/file", "e/f/"]);
+        let events = walk(Walker::new(&scratch.0).max_depth(Some(2)), |_| {});
+
+        assert!(!events.contains(&Event::Visit(scratch.0.join("a/b/c"))));
+        assert!(!complete(&events, &scratch.0.join("a/b")));
+        assert!(!complete(&events, &scratch.0.join("a")));
+        assert!(!complete(&events, &scratch.0.join("e")));
+        assert!(!complete(&events, &scratch.0));
+        assert!(complete(&events, &scratch.0.join("d")));
+    }
+
+    #[test]
+    fn read_errors_mark_the_directory_and_its_ancestors_incomplete() {
+        let scratch = Scratch::new("errors").with(&["a/gone/file", "a/b/file", "c/file"]);
+        let gone = scratch.0.join("a/gone");
+        // The directory is visited before it is read, so removing it then makes the read fail
+        let events = walk(Walker::new(&scratch.0), |entry| {
+            if entry.path() == gone {
+                std::fs::remove_dir_all(&gone).unwrap();
+            }
+        });
+
+        assert!(events.contains(&Event::Error(Some(gone.clone()))));
+        assert!(!complete(&events, &gone));
+        assert!(!complete(&events, &scratch.0.join("a")));
+        assert!(!complete(&events, &scratch.0));
+        assert!(complete(&events, &scratch.0.join("a/b")));
+        assert!(complete(&events, &scratch.0.join("c")));
+    }
+
+    #[test]
+    fn links_looping_back_to_an_ancestor_are_reported() {
+        let scratch = Scratch::new("loop").with(&["a/b/file"]);
+        std::os::unix::fs::symlink(&scratch.0, scratch.0.join("a/b/up")).unwrap();
+        std::os::unix::fs::symlink(scratch.0.join("a/b"), scratch.0.join("a/b/self")).unwrap();
+
+        let events = walk(Walker::new(&scratch.0).follow_links(true), |_| {});
+        assert!(events.contains(&Event::Loop(scratch.0.clone())));
+        assert!(events.contains(&Event::Loop(scratch.0.join("a/b"))));
+        assert!(!events.contains(&Event::Visit(scratch.0.join("a/b/up"))));
+
+        // Without following links, the links are plain entries
+        let events = walk(Walker::new(&scratch.0), |_| {});
+        assert!(events.contains(&Event::Visit(scratch.0.join("a/b/up"))));
+        assert!(!events.iter().any(|e| matches!(e, Event::Loop(_))));
+    }
+
+    #[test]
+    fn other_filesystems_are_skipped_when_asked() {
+        let root = Path::new("/");
+        let proc = Path::new("/proc");
+        let device = |path: &Path| std::fs::metadata(path).map(|metadata| metadata.dev());
+        match (device(root), device(proc)) {
+            (Ok(root), Ok(proc)) if root != proc => {}
+            _ => return,
+        }
+
+        let events = walk(Walker::new(root).max_depth(Some(1)), |_| {});
+        assert!(events.contains(&Event::Visit(proc.to_path_buf())));
+
+        let events = walk(
+            Walker::new(root).max_depth(Some(1)).same_file_system(true),
+            |_| {},
+        );
+        assert!(!events.contains(&Event::Visit(proc.to_path_buf())));
+        assert!(!finished(&events, proc));
+    }
+}
@@ -1,158 +1,455 @@
 mod counter;
 mod filesystem;
 mod models;
+mod scan;
 mod users;
+mod writer;
 
-use crate::models::definitions::DbModel;
+use crate::models::definitions::{ErrorPhase, ScanError, ScanStatus};
 use clap::Parser;
-use rayon::prelude::*;
 
 #[derive(clap::Parser, Default, Debug)]
 #[clap(author = "Dheshan Mohandass", version, about)]
 /// A CLI tool for tracking disk usage.
 struct Arguments {
-    /// The root directory to track.
+    /// The root directory to track. Relative paths, `.`, `..` and trailing slashes are normalized.
     #[clap(short, long)]
-    root_dir: String,
+    root_dir: std::path::PathBuf,
     /// Enable debug mode.
     #[clap(short, long)]
     debug: bool,
+    /// The number of records to write to the database in a single batch.
+    #[clap(long, default_value = "5000")]
+    batch_size: usize,
+    /// The maximum number of milliseconds a record may wait before being written.
+    #[clap(long, default_value = "1000")]
+    flush_interval_ms: u64,
+    /// The number of times a write failing with a transient error, such as a deadlock, is retried
+    /// after the first attempt before its records are given up on.
+    #[clap(long, default_value = "5")]
+    max_retries: u32,
+    /// Keep rows for files and directories that were not seen by this scan.
+    #[clap(long)]
+    no_reconcile: bool,
+    /// Follow symbolic links, crawling targets that lie outside the root directory.
+    #[clap(long)]
+    follow_symlinks: bool,
+    /// Do not cross into other filesystems (such as NFS, /proc or bind mounts) below the root directory.
+    #[clap(short = 'x', long)]
+    one_file_system: bool,
+    /// Skip entries matching this glob. Globs without a `/` match entry names, others match full paths.
+    #[clap(short, long)]
+    exclude: Vec<String>,
+    /// Skip entries whose full path matches this regular expression.
+    #[clap(long)]
+    exclude_regex: Vec<String>,
+    /// Read exclusion rules from this file, using gitignore semantics relative to the root directory,
+    /// in addition to the root's `.diskusageignore`.
+    #[clap(long)]
+    ignore_file: Vec<std::path::PathBuf>,
+    /// The number of threads reading directories. Defaults to the number of CPUs.
+    #[clap(long, default_value = "0")]
+    threads: usize,
+    /// Do not crawl entries more than this many levels below the root directory. Implies
+    /// --no-reconcile, and directories with unread contents get no totals.
+    #[clap(long)]
+    max_depth: Option<usize>,
+    /// Walk and stat everything without connecting to the database, then print a summary.
+    #[clap(long)]
+    dry_run: bool,
+    /// Continue the most recent unfinished scan of the root directory, skipping the top-level
+    /// subtrees it already finished.
+    #[clap(long, conflicts_with = "dry_run")]
+    resume: bool,
+    /// The maximum number of metadata operations (stat, readlink) per second, across all threads.
+    #[clap(long)]
+    max_stats_per_second: Option<u64>,
+    /// The maximum number of directories read at the same time.
+    #[clap(long)]
+    max_concurrent_reads: Option<usize>,
+    /// Slow down when the latency of metadata operations rises, e.g. on a busy metadata server.
+    #[clap(long)]
+    adaptive_throttle: bool,
 }
 
-/// Ensure that the user exists in the database.
-/// If the user does not exist, insert the user into the database.
-/// Users are stored in a cache to prevent querying the database for the same user multiple times.
+/// Queue an error to be recorded in the database, so that it is not silently dropped.
 ///
 /// Arguments
-/// * `owner` - The user id to check.
-/// * `pool` - The database connection pool.
-/// * `cache` - The cache to store user ids.
+/// * `error` - The error to record.
+/// * `sender` - The channel to the batch writer.
+fn report_error(error: ScanError, sender: &tokio::sync::mpsc::Sender<writer::batch::Record>) {
+    if let Err(e) = sender.blocking_send(writer::batch::Record::Error(error)) {
+        log::error!("Error queueing scan error: {:?}", e);
+    }
+}
+
+/// Process an error returned by the walker, such as an unreadable directory or a symlink loop.
 ///
-/// Returns
-/// * Ok(()) if the user exists or is inserted successfully.
-/// * Err(sqlx::Error) if an error occurs while inserting the user.
-async fn ensure_user_exists(
-    owner: Option<i32>,
-    pool: std::sync::Arc<sqlx::Pool<sqlx::Postgres>>,
-    cache: std::sync::Arc<dashmap::DashSet<i32>>,
-) -> Result<(), sqlx::Error> {
-    if let Some(owner_id) = owner {
-        // Check if the user exists in the cache
-        if let Some(_) = cache.get(&owner_id) {
-            return Ok(());
-        }
-        let select_user_where_clause = format!("WHERE user_id = {}", owner_id);
-        let user = models::definitions::User::select_where(&pool, &select_user_where_clause)
-            .await
-            .unwrap_or_default();
-
-        if user.is_empty() {
-            let user = models::definitions::User {
-                user_id: owner_id,
-                username: users::username::get_username(owner_id as u32),
-            };
-            user.insert(&pool).await?;
-        }
+/// Arguments
+/// * `error` - The walker error.
+/// * `scan_id` - The id of the current scan.
+/// * `sender` - The channel to the batch writer.
+fn process_walk_error(
+    error: filesystem::walker::Error,
+    scan_id: i32,
+    sender: &tokio::sync::mpsc::Sender<writer::batch::Record>,
+) {
+    let path = error.path().unwrap_or(std::path::Path::new(""));
+    log::warn!("Error walking {:?}: {}", path, error);
 
-        cache.insert(owner_id);
-    }
-    Ok(())
+    let scan_error = match (error.loop_ancestor(), error.io_error()) {
+        (Some(ancestor), _) => ScanError {
+            error_id: 0,
+            scan_id: Some(scan_id),
+            path: db::paths::encode(path),
+            error_kind: "FilesystemLoop".to_string(),
+            errno: None,
+            phase: ErrorPhase::Walk.as_str().to_string(),
+            message: Some(format!("loops back to {}", db::paths::encode(ancestor))),
+            occurred_at: chrono::Utc::now().naive_utc(),
+        },
+        (None, Some(io_error)) => ScanError::from_io(scan_id, path, ErrorPhase::Walk, io_error),
+        (None, None) => return,
+    };
+    report_error(scan_error, sender);
 }
 
 /// Process a directory entry.
-/// Insert the directory into the database. If the directory already exists, update the directory.
+/// Queue the directory to be written to the database. If the directory already exists, it is updated.
 ///
 /// Arguments
 /// * `entry` - The directory entry to process.
-/// * `pool` - The database connection pool.
-/// * `handle` - The tokio runtime handle.
-/// * `cache` - The cache to store user ids.
+/// * `scan_id` - The id of the current scan.
+/// * `mounts` - The mount table, used to record the mount point of the directory.
+/// * `sender` - The channel to the batch writer.
 ///
 /// Returns
 /// * None
 fn process_directory(
-    entry: walkdir::DirEntry,
-    pool: std::sync::Arc<sqlx::Pool<sqlx::Postgres>>,
-    handle: tokio::runtime::Handle,
-    cache: std::sync::Arc<dashmap::DashSet<i32>>,
+    entry: filesystem::walker::DirEntry,
+    scan_id: i32,
+    mounts: &filesystem::mounts::MountTable,
+    sender: &tokio::sync::mpsc::Sender<writer::batch::Record>,
 ) {
     let dir_path = entry.path();
-    let owner = filesystem::fetch::owner(dir_path).map(|x| x as i32);
+    let attributes = match entry.metadata() {
+        Ok(metadata) => Some(filesystem::fetch::attributes(&metadata)),
+        Err(e) => {
+            report_error(
+                ScanError::from_io(scan_id, dir_path, ErrorPhase::Stat, &e),
+                sender,
+            );
+            None
+        }
+    };
     let parent_dir = dir_path.parent().unwrap_or(std::path::Path::new("/"));
+    let mount = mounts.lookup(dir_path, attributes.as_ref().map(|a| a.device));
 
     let directory = models::definitions::Directory {
-        directory_id: dir_path.to_string_lossy().to_string(),
-        owner_id: owner,
-        parent_id: Some(parent_dir.to_string_lossy().to_string()),
+        directory_id: db::paths::encode(dir_path),
+        owner_id: attributes.as_ref().map(|a| a.owner as i32),
+        parent_id: Some(db::paths::encode(parent_dir)),
+        mount_point: mount.map(|mount| db::paths::encode(&mount.mount_point)),
+        fs_type: mount.map(|mount| mount.fs_type.clone()),
+        group_id: attributes.as_ref().map(|a| a.group as i32),
+        mode: attributes.as_ref().map(|a| a.mode as i32),
+        scan_id: Some(scan_id),
+        raw_path: db::paths::raw(dir_path),
     };
 
-    handle.block_on(async move {
-        if let Err(e) = ensure_user_exists(owner, pool.clone(), cache.clone()).await {
-            log::error!("Failed to insert user: {:?}", e);
-            return;
-        }
-        if let Err(e) = directory.insert(&pool).await {
-            log::error!("Error inserting directory: {:?}", e);
-        }
-    });
+    if let Err(e) = sender.blocking_send(writer::batch::Record::Directory(directory)) {
+        log::error!("Error queueing directory: {:?}", e);
+    }
 }
 
 /// Process a file entry.
-/// Insert the file into the database. If the file already exists, update the file.
-/// If the file is not inserted, retry the insert. Assume the file is not inserted due to
-/// its parent directory not being inserted.
+/// Queue the file to be written to the database. If the file already exists, it is updated.
 ///
 /// Arguments
 /// * `entry` - The file entry to process.
-/// * `pool` - The database connection pool.
-/// * `handle` - The tokio runtime handle.
-/// * `cache` - The cache to store user ids.
+/// * `scan_id` - The id of the current scan.
+/// * `rollups` - The directory totals, in which the file is counted.
+/// * `sender` - The channel to the batch writer.
 ///
 /// Returns
 /// * None
 fn process_file(
-    entry: walkdir::DirEntry,
-    pool: std::sync::Arc<sqlx::Pool<sqlx::Postgres>>,
-    handle: tokio::runtime::Handle,
-    cache: std::sync::Arc<dashmap::DashSet<i32>>,
+    entry: filesystem::walker::DirEntry,
+    scan_id: i32,
+    rollups: &scan::rollup::Rollups,
+    sender: &tokio::sync::mpsc::Sender<writer::batch::Record>,
 ) {
     let file_path = entry.path();
-    let owner = filesystem::fetch::owner(file_path).map(|x| x as i32);
-    let file_size = filesystem::fetch::file_size(file_path).unwrap_or_default();
-    let last_modified = filesystem::fetch::last_modified(file_path).ok();
+    let attributes = match entry.metadata() {
+        Ok(metadata) => Some(filesystem::fetch::attributes(&metadata)),
+        Err(e) => {
+            report_error(
+                ScanError::from_io(scan_id, file_path, ErrorPhase::Stat, &e),
+                sender,
+            );
+            None
+        }
+    };
     let parent_dir = file_path.parent().unwrap_or(std::path::Path::new("/"));
+    if let Some(attributes) = &attributes {
+        rollups.add_file(
+            parent_dir,
+            attributes.size as i64,
+            attributes.allocated_size as i64,
+        );
+    }
 
     let file = models::definitions::File {
-        file_id: file_path.to_string_lossy().to_string(),
-        name: file_path.file_name().unwrap().to_string_lossy().to_string(),
-        size: file_size as i64,
-        owner_id: owner,
-        directory_id: parent_dir.to_string_lossy().to_string(),
-        last_modified,
+        file_id: db::paths::encode(file_path),
+        name: db::paths::encode(std::path::Path::new(file_path.file_name().unwrap())),
+        size: attributes.as_ref().map_or(0, |a| a.size as i64),
+        allocated_size: attributes.as_ref().map(|a| a.allocated_size as i64),
+        owner_id: attributes.as_ref().map(|a| a.owner as i32),
+        group_id: attributes.as_ref().map(|a| a.group as i32),
+        mode: attributes.as_ref().map(|a| a.mode as i32),
+        directory_id: db::paths::encode(parent_dir),
+        last_modified: attributes.as_ref().and_then(|a| a.last_modified),
+        device: attributes.as_ref().map(|a| a.device as i64),
+        inode: attributes.as_ref().map(|a| a.inode as i64),
+        nlink: attributes.as_ref().map(|a| a.nlink as i64),
+        atime_ns: attributes.as_ref().and_then(|a| a.atime_ns),
+        mtime_ns: attributes.as_ref().and_then(|a| a.mtime_ns),
+        ctime_ns: attributes.as_ref().and_then(|a| a.ctime_ns),
+        btime_ns: attributes.as_ref().and_then(|a| a.btime_ns),
+        scan_id: Some(scan_id),
+        raw_path: db::paths::raw(file_path),
     };
 
-    handle.block_on(async move {
-        if let Err(e) = ensure_user_exists(owner, pool.clone(), cache.clone()).await {
-            log::error!("Failed to insert user: {:?}", e);
+    if let Err(e) = sender.blocking_send(writer::batch::Record::File(file)) {
+        log::error!("Error queueing file: {:?}", e);
+    }
+}
+
+/// Process a symbolic link.
+/// Queue the link itself to be written to the database, whether or not it is followed.
+///
+/// Arguments
+/// * `link_path` - The path to the symbolic link.
+/// * `scan_id` - The id of the current scan.
+/// * `throttle` - The throttle pacing metadata operations.
+/// * `sender` - The channel to the batch writer.
+///
+/// Returns
+/// * None
+fn process_symlink(
+    link_path: &std::path::Path,
+    scan_id: i32,
+    throttle: &filesystem::throttle::Throttle,
+    sender: &tokio::sync::mpsc::Sender<writer::batch::Record>,
+) {
+    let link = match filesystem::fetch::symlink(link_path, throttle) {
+        Ok(link) => link,
+        Err(e) => {
+            report_error(
+                ScanError::from_io(scan_id, link_path, ErrorPhase::Stat, &e),
+                sender,
+            );
             return;
         }
+    };
+    let parent_dir = link_path.parent().unwrap_or(std::path::Path::new("/"));
 
-        loop {
-            let result = file.insert(&pool).await;
-            // If PgDatabaseError is returned, retry the insert
-            if let Err(sqlx::Error::Database(_)) = result {
-                // sleep for random time between 1 and 5 seconds
-                let sleep_time = rand::random::<u64>() % 5 + 1;
-                tokio::time::sleep(tokio::time::Duration::from_secs(sleep_time)).await;
-                continue;
-            }
-            break;
-        }
+    let symlink = models::definitions::Symlink {
+        symlink_id: db::paths::encode(link_path),
+        name: db::paths::encode(std::path::Path::new(
+            link_path.file_name().unwrap_or_default(),
+        )),
+        target: db::paths::encode(&link.target),
+        dangling: link.dangling,
+        size: link.size as i64,
+        owner_id: Some(link.owner as i32),
+        directory_id: db::paths::encode(parent_dir),
+        scan_id: Some(scan_id),
+        raw_path: db::paths::raw(link_path),
+        raw_target: db::paths::raw(&link.target),
+    };
 
-        if let Err(e) = file.insert(&pool).await {
-            log::error!("Error inserting file: {:?}", e);
-        }
+    if let Err(e) = sender.blocking_send(writer::batch::Record::Symlink(symlink)) {
+        log::error!("Error queueing symlink: {:?}", e);
+    }
+}
+
+/// Walk the root directory, queueing a record for every directory, file, symlink and error.
+/// The channel is closed once the walk is done.
+///
+/// Arguments
+/// * `args` - The command line arguments.
+/// * `exclude_rules` - The rules deciding which entries are skipped.
+/// * `completed` - The top-level subtrees an earlier run of the scan finished, which are skipped.
+/// * `shutdown` - Stops the walk when a signal is received.
+/// * `throttle` - Paces metadata operations and directory reads.
+/// * `scan_id` - The id of the current scan.
+/// * `sender` - The channel to the batch writer.
+///
+/// Returns
+/// * None
+fn crawl(
+    args: &Arguments,
+    exclude_rules: &filesystem::exclude::ExcludeRules,
+    completed: &scan::checkpoint::Completed,
+    shutdown: &scan::shutdown::Shutdown,
+    throttle: std::sync::Arc<filesystem::throttle::Throttle>,
+    scan_id: i32,
+    sender: tokio::sync::mpsc::Sender<writer::batch::Record>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!(
+        "Starting disk usage tracking for: {}",
+        args.root_dir.display()
+    );
+    let mounts = filesystem::mounts::MountTable::read().unwrap_or_else(|e| {
+        log::warn!(
+            "Failed to read mount table, mount points will not be recorded: {}",
+            e
+        );
+        filesystem::mounts::MountTable::default()
+    });
+    let follow_symlinks = args.follow_symlinks;
+    let root = args.root_dir.as_path();
+    let follow_policy =
+        filesystem::symlinks::FollowPolicy::new(root, std::sync::Arc::clone(&throttle))?;
+    let rollups = scan::rollup::Rollups::new(root);
+    let walker = filesystem::walker::Walker::new(root)
+        .follow_links(follow_symlinks)
+        .same_file_system(args.one_file_system)
+        .max_depth(args.max_depth)
+        .stop_flag(shutdown.stop_flag())
+        .throttle(std::sync::Arc::clone(&throttle));
+    let threads = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?;
+    tokio::task::block_in_place(|| {
+        threads.install(|| {
+            walker.run(
+                |entry| {
+                    // Excluded subtrees are pruned before the walker descends into them
+                    if exclude_rules.is_excluded(entry.path(), entry.file_type().is_dir()) {
+                        return false;
+                    }
+                    if entry.depth() == 1 && entry.file_type().is_dir() {
+                        if let Some(rollup) = completed.get(entry.path()) {
+                            rollups.skip(entry.path(), rollup);
+                            return false;
+                        }
+                    }
+                    if !follow_symlinks {
+                        return true;
+                    }
+                    // Links that are not followed are pruned here, so they are recorded here as well
+                    let is_link = entry.path_is_symlink();
+                    if (is_link && !follow_policy.should_follow(entry.path()))
+                        || !follow_policy.first_visit(entry)
+                    {
+                        if is_link {
+                            process_symlink(entry.path(), scan_id, &throttle, &sender);
+                        }
+                        return false;
+                    }
+                    true
+                },
+                |entry| {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            // When following links, dangling links and loops are reported as errors,
+                            // but the links themselves are still recorded
+                            if let Some(path) = e.path() {
+                                if follow_symlinks && throttle.stat(|| path.is_symlink()) {
+                                    process_symlink(path, scan_id, &throttle, &sender);
+                                    if !throttle.stat(|| path.exists()) {
+                                        return;
+                                    }
+                                }
+                            }
+                            process_walk_error(e, scan_id, &sender);
+                            return;
+                        }
+                    };
+                    if entry.depth() > 0 && entry.path_is_symlink() {
+                        process_symlink(entry.path(), scan_id, &throttle, &sender);
+                    }
+                    // The walker visits a directory before reading it, so a directory always
+                    // reaches the writer ahead of its contents
+                    if entry.file_type().is_dir() {
+                        process_directory(entry, scan_id, &mounts, &sender);
+                    } else if entry.file_type().is_file() {
+                        process_file(entry, scan_id, &rollups, &sender);
+                    }
+                },
+                |directory, complete| {
+                    // Totals of a partly read subtree would undercount it, and a checkpoint
+                    // would keep --resume from reading it again
+                    if !complete {
+                        rollups.abandon(directory);
+                        return;
+                    }
+                    let rollup = rollups.finish(directory);
+                    if let Err(e) = sender.blocking_send(writer::batch::Record::Rollup(rollup)) {
+                        log::error!("Error queueing directory totals: {:?}", e);
+                    }
+                    // Queued after everything below the subtree, so it is written after it as well
+                    if scan::checkpoint::is_checkpointed(root, directory) {
+                        let checkpoint = scan::checkpoint::checkpoint(scan_id, directory);
+                        if let Err(e) =
+                            sender.blocking_send(writer::batch::Record::Checkpoint(checkpoint))
+                        {
+                            log::error!("Error queueing checkpoint: {:?}", e);
+                        }
+                    }
+                },
+            )
+        })
     });
+    Ok(())
+}
+
+/// Walk the root directory without touching the database, and print a summary of what was found.
+///
+/// Arguments
+/// * `args` - The command line arguments.
+/// * `exclude_rules` - The rules deciding which entries are skipped.
+/// * `shutdown` - Stops the walk when a signal is received.
+/// * `throttle` - Paces metadata operations and directory reads.
+///
+/// Returns
+/// * None
+async fn dry_run(
+    args: &Arguments,
+    exclude_rules: &filesystem::exclude::ExcludeRules,
+    shutdown: &scan::shutdown::Shutdown,
+    throttle: std::sync::Arc<filesystem::throttle::Throttle>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!("Dry run: nothing will be written to the database");
+    let (sender, receiver) = tokio::sync::mpsc::channel(args.batch_size.max(1) * 4);
+    let summary_handle = writer::summary::spawn(receiver);
+    let completed = scan::checkpoint::Completed::default();
+    crawl(
+        args,
+        exclude_rules,
+        &completed,
+        shutdown,
+        throttle,
+        0,
+        sender,
+    )?;
+    let summary = summary_handle.await?;
+
+    if shutdown.requested() {
+        println!(
+            "Summary of {} (interrupted, partial):",
+            args.root_dir.display()
+        );
+    } else {
+        println!("Summary of {}:", args.root_dir.display());
+    }
+    println!("{}", summary.totals_table());
+    println!("Top owners by size:");
+    println!("{}", summary.owners_table());
+    Ok(())
 }
 
 #[tokio::main]
@@ -161,17 +458,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .filter_level(log::LevelFilter::Info)
         .init();
 
-    let args = Arguments::parse();
+    let mut args = Arguments::parse();
     log::info!("{:?}", args);
 
-    let root_dir = args.root_dir;
+    // Normalized the same way as the paths given to estimate, so that both agree on its id
+    args.root_dir = db::paths::normalize(&args.root_dir)?;
+    let root_dir = args.root_dir.clone();
 
     // Check if root directory exists
-    if !std::path::Path::new(&root_dir).exists() {
-        log::error!("Root directory does not exist: {}", root_dir);
+    if !root_dir.exists() {
+        log::error!("Root directory does not exist: {}", root_dir.display());
         return Err("Root directory does not exist".into());
     }
 
+    let exclude_rules = filesystem::exclude::ExcludeRules::new(
+        &root_dir,
+        &args.exclude,
+        &args.exclude_regex,
+        &args.ignore_file,
+    )?;
+    for rule in exclude_rules.descriptions() {
+        log::info!("Excluding: {}", rule);
+    }
+
+    // Installed before anything is walked, so that a signal never kills the crawler mid-write
+    let shutdown = scan::shutdown::Shutdown::listen()?;
+
+    let throttle = std::sync::Arc::new(filesystem::throttle::Throttle::new(
+        filesystem::throttle::Config {
+            max_stats_per_second: args.max_stats_per_second,
+            max_concurrent_reads: args.max_concurrent_reads,
+            adaptive: args.adaptive_throttle,
+        },
+    ));
+    log::info!("Filesystem throttle: {}", throttle.describe());
+
+    if args.dry_run {
+        dry_run(&args, &exclude_rules, &shutdown, throttle).await?;
+        shutdown.exit_if_requested();
+        return Ok(());
+    }
+
     // get the database url from the environment
     let database_url = match std::env::var("DATABASE_URL") {
         Ok(url) => url,
@@ -196,24 +523,93 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Spawn a thread to query files processed periodically
     let pool_c = std::sync::Arc::clone(&pool);
     let handle_c: tokio::runtime::Handle = handle.clone();
-    counter::logger::logger_thread(handle_c, pool_c).await;
-
-    let cache: std::sync::Arc<dashmap::DashSet<i32>> = std::sync::Arc::new(dashmap::DashSet::new());
-
-    log::info!("Starting disk usage tracking for: {}", root_dir);
-    walkdir::WalkDir::new(root_dir)
-        .into_iter()
-        .par_bridge() // Allows rayon to process entries in parallel
-        .filter_map(|entry| entry.ok())
-        .for_each(|entry| {
-            let pool = std::sync::Arc::clone(&pool);
-            let handle = handle.clone();
-            if entry.file_type().is_dir() {
-                process_directory(entry, pool, handle, cache.clone());
-            } else if entry.file_type().is_file() {
-                process_file(entry, pool, handle, cache.clone());
-            }
-        });
+    counter::logger::logger_thread(handle_c, pool_c, std::sync::Arc::clone(&throttle)).await;
+
+    // The root is recorded in the same encoding as the paths below it
+    let root_id = db::paths::encode(&root_dir);
+    let (mut scan, completed) = if args.resume {
+        scan::session::resume(&pool, &root_id, exclude_rules.descriptions()).await?
+    } else {
+        let scan = scan::session::begin(&pool, &root_id, exclude_rules.descriptions()).await?;
+        (scan, scan::checkpoint::Completed::default())
+    };
+
+    // Records are handed to a single writer task, which flushes them in batches
+    let writer_config = writer::batch::Config {
+        batch_size: args.batch_size.max(1),
+        flush_interval: std::time::Duration::from_millis(args.flush_interval_ms.max(1)),
+        retry: writer::retry::Policy {
+            max_attempts: args.max_retries.saturating_add(1),
+            ..Default::default()
+        },
+    };
+    let (sender, receiver) = tokio::sync::mpsc::channel(writer_config.batch_size * 4);
+    let writer_handle = writer::batch::spawn(std::sync::Arc::clone(&pool), receiver, writer_config);
+
+    crawl(
+        &args,
+        &exclude_rules,
+        &completed,
+        &shutdown,
+        throttle,
+        scan.scan_id,
+        sender,
+    )?;
+    // Decided once the walk returns: a signal arriving while the writer drains does not make
+    // a finished walk partial
+    let interrupted = shutdown.requested();
+
+    // The channel is closed once the walk is done, so the writer flushes what remains and exits
+    let mut writer_stats = match writer_handle.await {
+        Ok(stats) => stats,
+        Err(e) => {
+            log::error!("Writer task failed: {:?}", e);
+            let stats = writer::batch::Stats::default();
+            scan::session::end(&pool, &mut scan, &stats, ScanStatus::Failed).await?;
+            return Err(e.into());
+        }
+    };
+    log::info!(
+        "Wrote {} directories, {} files, {} symlinks and {} errors in {} batches",
+        writer_stats.directories,
+        writer_stats.files,
+        writer_stats.symlinks,
+        writer_stats.errors,
+        writer_stats.batches
+    );
+    if !writer_stats.dead_letters.is_empty() {
+        log::warn!(
+            "{} records could not be written and were recorded in scan_errors with phase '{}':",
+            writer_stats.dead_letters.len(),
+            ErrorPhase::Insert.as_str()
+        );
+        for dead_letter in &writer_stats.dead_letters {
+            log::warn!(
+                "  {} ({}): {}",
+                dead_letter.path,
+                dead_letter.error_kind,
+                dead_letter.message.as_deref().unwrap_or("")
+            );
+        }
+    }
+    completed.add_to(&mut writer_stats);
+    if interrupted {
+        scan::session::end(&pool, &mut scan, &writer_stats, ScanStatus::Interrupted).await?;
+        log::warn!(
+            "Scan {} was interrupted, continue it with --resume",
+            scan.scan_id
+        );
+        shutdown.exit_if_requested();
+    }
+    scan::session::end(&pool, &mut scan, &writer_stats, ScanStatus::Completed).await?;
+
+    if args.max_depth.is_some() {
+        // Rows below the maximum depth were not seen because they were not read
+        log::info!("Not removing unseen rows, since --max-depth limits the scan");
+    } else if !args.no_reconcile {
+        scan::reconcile::remove_unseen(&pool, &scan).await?;
+    }
+    shutdown.exit_if_requested();
 
     Ok(())
 }
@@ -1,49 +1,303 @@
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::Row;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct User {
     pub user_id: i32,
     pub username: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Group {
+    pub group_id: i32,
+    pub groupname: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Directory {
     pub directory_id: String,
     pub owner_id: Option<i32>,
     pub parent_id: Option<String>,
+    pub mount_point: Option<String>,
+    pub fs_type: Option<String>,
+    pub group_id: Option<i32>,
+    /// The permission bits, including the setuid, setgid and sticky bits.
+    pub mode: Option<i32>,
+    pub scan_id: Option<i32>,
+    /// The path as raw bytes. `directory_id` holds it encoded by `db::paths::encode`.
+    pub raw_path: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct File {
     pub file_id: String,
     pub name: String,
     pub size: i64,
+    pub allocated_size: Option<i64>,
     pub owner_id: Option<i32>,
+    pub group_id: Option<i32>,
+    /// The permission bits, including the setuid, setgid and sticky bits.
+    pub mode: Option<i32>,
     pub directory_id: String,
     pub last_modified: Option<chrono::NaiveDateTime>,
+    pub device: Option<i64>,
+    pub inode: Option<i64>,
+    pub nlink: Option<i64>,
+    /// Access, modification, status change and birth times, in nanoseconds since the Unix epoch.
+    pub atime_ns: Option<i64>,
+    pub mtime_ns: Option<i64>,
+    pub ctime_ns: Option<i64>,
+    pub btime_ns: Option<i64>,
+    pub scan_id: Option<i32>,
+    /// The path as raw bytes. `file_id` holds it encoded by `db::paths::encode`.
+    pub raw_path: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Symlink {
+    pub symlink_id: String,
+    pub name: String,
+    pub target: String,
+    pub dangling: bool,
+    pub size: i64,
+    pub owner_id: Option<i32>,
+    pub directory_id: String,
+    pub scan_id: Option<i32>,
+    /// The path and target as raw bytes. `symlink_id` and `target` hold them encoded by
+    /// `db::paths::encode`.
+    pub raw_path: Vec<u8>,
+    pub raw_target: Vec<u8>,
+}
+
+/// Totals for a directory, computed by the crawler once its whole subtree has been walked.
+/// `direct_*` count the entries in the directory itself, `total_*` those in its whole subtree.
+/// They are stored on the directory row.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Rollup {
+    pub directory_id: String,
+    pub direct_files: i64,
+    pub direct_subdirectories: i64,
+    pub direct_bytes: i64,
+    pub direct_allocated_bytes: i64,
+    pub total_files: i64,
+    pub total_subdirectories: i64,
+    pub total_bytes: i64,
+    pub total_allocated_bytes: i64,
+}
+
+/// A top-level subtree of the root that a scan has finished walking and writing.
+/// A resumed scan skips the subtrees it holds checkpoints for.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub scan_id: i32,
+    pub path: String,
+    pub completed_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Scan {
+    pub scan_id: i32,
+    pub root: String,
+    pub host: Option<String>,
+    pub started_at: chrono::NaiveDateTime,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub status: String,
+    pub file_count: i64,
+    pub directory_count: i64,
+    pub total_bytes: i64,
+    pub exclude_rules: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct ScanError {
+    /// Assigned by the database; zero for errors that have not been inserted yet.
+    pub error_id: i64,
+    pub scan_id: Option<i32>,
+    pub path: String,
+    pub error_kind: String,
+    pub errno: Option<i32>,
+    pub phase: String,
+    pub message: Option<String>,
+    pub occurred_at: chrono::NaiveDateTime,
+}
+
+/// The stage of the crawl in which an error occurred, as stored in `scan_errors.phase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPhase {
+    /// Reading a directory, or following a link, while walking the tree.
+    Walk,
+    /// Reading the metadata of an entry.
+    Stat,
+    /// Writing an entry to the database.
+    Insert,
+}
+
+impl ErrorPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorPhase::Walk => "walk",
+            ErrorPhase::Stat => "stat",
+            ErrorPhase::Insert => "insert",
+        }
+    }
+}
+
+impl ScanError {
+    /// Build an error record from an I/O error.
+    ///
+    /// * `scan_id` - The id of the current scan.
+    /// * `path` - The path the error occurred on.
+    /// * `phase` - The stage of the crawl the error occurred in.
+    /// * `error` - The I/O error.
+    pub fn from_io(
+        scan_id: i32,
+        path: &std::path::Path,
+        phase: ErrorPhase,
+        error: &std::io::Error,
+    ) -> ScanError {
+        ScanError {
+            error_id: 0,
+            scan_id: Some(scan_id),
+            path: db::paths::encode(path),
+            error_kind: format!("{:?}", error.kind()),
+            errno: error.raw_os_error(),
+            phase: phase.as_str().to_string(),
+            message: Some(error.to_string()),
+            occurred_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+impl ScanError {
+    /// Build an error record from a failed database write.
+    ///
+    /// * `scan_id` - The id of the scan the entry belongs to.
+    /// * `path` - The path of the entry that could not be written.
+    /// * `error` - The database error.
+    pub fn from_sqlx(scan_id: Option<i32>, path: &str, error: &sqlx::Error) -> ScanError {
+        let error_kind = match error {
+            sqlx::Error::Database(e) => e.code().map(|code| code.to_string()),
+            _ => None,
+        };
+        ScanError {
+            error_id: 0,
+            scan_id,
+            path: path.to_string(),
+            error_kind: error_kind.unwrap_or_else(|| "Database".to_string()),
+            errno: None,
+            phase: ErrorPhase::Insert.as_str().to_string(),
+            message: Some(error.to_string()),
+            occurred_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// The lifecycle states of a scan, as stored in `scans.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStatus {
+    Running,
+    Completed,
+    Failed,
+    /// Stopped by a signal before the walk finished. It can be continued with `--resume`.
+    Interrupted,
+}
+
+impl ScanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStatus::Running => "running",
+            ScanStatus::Completed => "completed",
+            ScanStatus::Failed => "failed",
+            ScanStatus::Interrupted => "interrupted",
+        }
+    }
 }
 
 #[async_trait::async_trait]
 pub trait DbModel {
     async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error>;
     async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error>;
+    // The crawler only writes rows, the deletes and reads below are for report tools
+    #[allow(dead_code)]
     async fn delete(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error>;
+    #[allow(dead_code)]
     async fn select(
         &self,
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
     ) -> Result<Box<Self>, sqlx::Error>;
+    #[allow(dead_code)]
     async fn select_all(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
     ) -> Result<Vec<Box<Self>>, sqlx::Error>;
+    #[allow(dead_code)]
     async fn select_where(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
-        where_clause: &str,
+        filter: &db::filter::Filter,
     ) -> Result<Vec<Box<Self>>, sqlx::Error>;
+    /// Stream every row of the table, reading them from the database as they are consumed.
+    /// Like `select_where_stream`, it must be called from within a Tokio runtime.
+    #[allow(dead_code)]
+    fn select_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> BoxStream<'static, Result<Box<Self>, sqlx::Error>>
+    where
+        Self: Sized,
+    {
+        Self::select_where_stream(pool, &db::filter::Filter::All)
+    }
+    /// Stream the rows matching a filter, reading them from the database as they are consumed.
+    /// The rows are read by a background task, at most `db::stream::BUFFER` rows ahead of the
+    /// consumer, so it must be called from within a Tokio runtime. The stream does not borrow
+    /// the pool or the filter.
+    #[allow(dead_code)]
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<Self>, sqlx::Error>>;
+    /// Insert many rows in a single statement where the model supports it. The bulk statements
+    /// leave rows that already exist untouched and do not count them.
+    ///
+    /// Models without a bulk statement write the rows one at a time with `insert`, so existing
+    /// rows get whatever `insert` does with them (most models update them), and every row is
+    /// counted.
+    ///
+    /// Returns the number of rows written.
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Self],
+    ) -> Result<u64, sqlx::Error>
+    where
+        Self: Sized + Sync,
+    {
+        let mut count = 0;
+        for row in rows {
+            row.insert(pool).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Insert many rows in a single statement where the model supports it, updating the rows
+    /// that already exist.
+    ///
+    /// Models without a bulk statement write the rows one at a time with `insert`.
+    ///
+    /// Returns the number of rows inserted or updated.
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Self],
+    ) -> Result<u64, sqlx::Error>
+    where
+        Self: Sized + Sync,
+    {
+        Self::insert_many(pool, rows).await
+    }
     async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error>;
 }
 
+#[allow(dead_code)]
 #[async_trait::async_trait]
 pub trait DbEstimateRow {
     async fn estimate_count(
@@ -51,6 +305,36 @@ pub trait DbEstimateRow {
     ) -> Result<i64, sqlx::Error>;
 }
 
+/// Write users in a single multi-row `UNNEST` statement.
+///
+/// * `pool` - The database connection pool.
+/// * `users` - The rows to write.
+/// * `on_conflict` - The `ON CONFLICT` clause deciding what happens to rows that already exist.
+///
+/// Returns the number of rows affected.
+async fn write_users(
+    pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    users: &[User],
+    on_conflict: &str,
+) -> Result<u64, sqlx::Error> {
+    let user_ids: Vec<i32> = users.iter().map(|u| u.user_id).collect();
+    let usernames: Vec<Option<String>> = users.iter().map(|u| u.username.clone()).collect();
+
+    let query = format!(
+        r#"
+        INSERT INTO users (user_id, username)
+        SELECT * FROM UNNEST($1::INT[], $2::TEXT[])
+        {on_conflict}
+        "#
+    );
+    let result = sqlx::query(&query)
+        .bind(user_ids)
+        .bind(usernames)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 #[async_trait::async_trait]
 impl DbModel for User {
     async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
@@ -102,15 +386,16 @@ impl DbModel for User {
         let users = sqlx::query_as!(User, "SELECT user_id, username FROM users")
             .fetch_all(pool)
             .await?;
-        Ok(users.into_iter().map(|u| Box::new(u)).collect())
+        Ok(users.into_iter().map(Box::new).collect())
     }
 
     async fn select_where(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
-        where_clause: &str,
+        filter: &db::filter::Filter,
     ) -> Result<Vec<Box<User>>, sqlx::Error> {
-        let query_string = format!("SELECT user_id, username FROM users WHERE {}", where_clause);
-        let rows = sqlx::query(&query_string).fetch_all(pool).await?;
+        let mut query = sqlx::QueryBuilder::new("SELECT user_id, username FROM users WHERE ");
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
 
         let users: Vec<Box<User>> = rows
             .into_iter()
@@ -124,6 +409,39 @@ impl DbModel for User {
         Ok(users)
     }
 
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<User>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new("SELECT user_id, username FROM users WHERE ");
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[User],
+    ) -> Result<u64, sqlx::Error> {
+        write_users(pool, rows, "ON CONFLICT (user_id) DO NOTHING").await
+    }
+
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[User],
+    ) -> Result<u64, sqlx::Error> {
+        write_users(
+            pool,
+            rows,
+            r#"
+            ON CONFLICT (user_id) DO UPDATE
+            SET username = EXCLUDED.username
+            "#,
+        )
+        .await
+    }
+
     async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
         let count = sqlx::query("SELECT COUNT(*) FROM users")
             .fetch_one(pool)
@@ -132,14 +450,214 @@ impl DbModel for User {
     }
 }
 
+/// Write groups in a single multi-row `UNNEST` statement.
+///
+/// * `pool` - The database connection pool.
+/// * `groups` - The rows to write.
+/// * `on_conflict` - The `ON CONFLICT` clause deciding what happens to rows that already exist.
+///
+/// Returns the number of rows affected.
+async fn write_groups(
+    pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    groups: &[Group],
+    on_conflict: &str,
+) -> Result<u64, sqlx::Error> {
+    let group_ids: Vec<i32> = groups.iter().map(|g| g.group_id).collect();
+    let groupnames: Vec<Option<String>> = groups.iter().map(|g| g.groupname.clone()).collect();
+
+    let query = format!(
+        r#"
+        INSERT INTO groups (group_id, groupname)
+        SELECT * FROM UNNEST($1::INT[], $2::TEXT[])
+        {on_conflict}
+        "#
+    );
+    let result = sqlx::query(&query)
+        .bind(group_ids)
+        .bind(groupnames)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[async_trait::async_trait]
+impl DbModel for Group {
+    async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO groups (group_id, groupname) VALUES ($1, $2) ON CONFLICT (group_id) DO UPDATE SET groupname = $2",
+            self.group_id,
+            self.groupname
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE groups SET groupname = $2 WHERE group_id = $1",
+            self.group_id,
+            self.groupname
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM groups WHERE group_id = $1", self.group_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn select(
+        &self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Box<Self>, sqlx::Error> {
+        let group = sqlx::query_as!(
+            Group,
+            "SELECT group_id, groupname FROM groups WHERE group_id = $1",
+            self.group_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Box::new(group))
+    }
+
+    async fn select_all(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Vec<Box<Self>>, sqlx::Error> {
+        let groups = sqlx::query_as!(Group, "SELECT group_id, groupname FROM groups")
+            .fetch_all(pool)
+            .await?;
+        Ok(groups.into_iter().map(Box::new).collect())
+    }
+
+    async fn select_where(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> Result<Vec<Box<Group>>, sqlx::Error> {
+        let mut query = sqlx::QueryBuilder::new("SELECT group_id, groupname FROM groups WHERE ");
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
+
+        let groups: Vec<Box<Group>> = rows
+            .into_iter()
+            .map(|row| {
+                Box::new(Group {
+                    group_id: row.get("group_id"),
+                    groupname: row.get("groupname"),
+                })
+            })
+            .collect();
+        Ok(groups)
+    }
+
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<Group>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new("SELECT group_id, groupname FROM groups WHERE ");
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Group],
+    ) -> Result<u64, sqlx::Error> {
+        write_groups(pool, rows, "ON CONFLICT (group_id) DO NOTHING").await
+    }
+
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Group],
+    ) -> Result<u64, sqlx::Error> {
+        write_groups(
+            pool,
+            rows,
+            r#"
+            ON CONFLICT (group_id) DO UPDATE
+            SET groupname = EXCLUDED.groupname
+            "#,
+        )
+        .await
+    }
+
+    async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query("SELECT COUNT(*) FROM groups")
+            .fetch_one(pool)
+            .await?;
+        Ok(count.get("count"))
+    }
+}
+
+/// Write directories in a single multi-row `UNNEST` statement.
+///
+/// * `pool` - The database connection pool.
+/// * `directories` - The rows to write.
+/// * `on_conflict` - The `ON CONFLICT` clause deciding what happens to rows that already exist.
+///
+/// Returns the number of rows affected.
+async fn write_directories(
+    pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    directories: &[Directory],
+    on_conflict: &str,
+) -> Result<u64, sqlx::Error> {
+    let directory_ids: Vec<String> = directories.iter().map(|d| d.directory_id.clone()).collect();
+    let owner_ids: Vec<Option<i32>> = directories.iter().map(|d| d.owner_id).collect();
+    let parent_ids: Vec<Option<String>> = directories.iter().map(|d| d.parent_id.clone()).collect();
+    let mount_points: Vec<Option<String>> =
+        directories.iter().map(|d| d.mount_point.clone()).collect();
+    let fs_types: Vec<Option<String>> = directories.iter().map(|d| d.fs_type.clone()).collect();
+    let group_ids: Vec<Option<i32>> = directories.iter().map(|d| d.group_id).collect();
+    let modes: Vec<Option<i32>> = directories.iter().map(|d| d.mode).collect();
+    let scan_ids: Vec<Option<i32>> = directories.iter().map(|d| d.scan_id).collect();
+    let raw_paths: Vec<Vec<u8>> = directories.iter().map(|d| d.raw_path.clone()).collect();
+
+    let query = format!(
+        r#"
+        INSERT INTO directories (directory_id, owner_id, parent_id, mount_point, fs_type, group_id, mode, scan_id, raw_path)
+        SELECT * FROM UNNEST(
+            $1::TEXT[], $2::INT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::INT[], $7::INT[], $8::INT[], $9::BYTEA[]
+        )
+        {on_conflict}
+        "#
+    );
+    let result = sqlx::query(&query)
+        .bind(directory_ids)
+        .bind(owner_ids)
+        .bind(parent_ids)
+        .bind(mount_points)
+        .bind(fs_types)
+        .bind(group_ids)
+        .bind(modes)
+        .bind(scan_ids)
+        .bind(raw_paths)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 #[async_trait::async_trait]
 impl DbModel for Directory {
+    /// Insert the directory, or update it. As in `upsert_many`, recorded totals are cleared
+    /// until a scan finishes the subtree again.
     async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
         sqlx::query!(
-            "INSERT INTO directories (directory_id, owner_id, parent_id) VALUES ($1, $2, $3) ON CONFLICT (directory_id) DO UPDATE SET owner_id = $2, parent_id = $3",
+            "INSERT INTO directories (directory_id, owner_id, parent_id, mount_point, fs_type, scan_id, group_id, mode, raw_path) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT (directory_id) DO UPDATE SET owner_id = $2, parent_id = $3, mount_point = $4, fs_type = $5, scan_id = $6, group_id = $7, mode = $8, raw_path = $9, direct_files = NULL, direct_subdirectories = NULL, direct_bytes = NULL, direct_allocated_bytes = NULL, total_files = NULL, total_subdirectories = NULL, total_bytes = NULL, total_allocated_bytes = NULL",
             self.directory_id,
             self.owner_id,
-            self.parent_id
+            self.parent_id,
+            self.mount_point,
+            self.fs_type,
+            self.scan_id,
+            self.group_id,
+            self.mode,
+            self.raw_path
         )
         .execute(pool)
         .await?;
@@ -148,10 +666,16 @@ impl DbModel for Directory {
 
     async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
         sqlx::query!(
-            "UPDATE directories SET owner_id = $2, parent_id = $3 WHERE directory_id = $1",
+            "UPDATE directories SET owner_id = $2, parent_id = $3, mount_point = $4, fs_type = $5, scan_id = $6, group_id = $7, mode = $8, raw_path = $9 WHERE directory_id = $1",
             self.directory_id,
             self.owner_id,
-            self.parent_id
+            self.parent_id,
+            self.mount_point,
+            self.fs_type,
+            self.scan_id,
+            self.group_id,
+            self.mode,
+            self.raw_path
         )
         .execute(pool)
         .await?;
@@ -174,7 +698,7 @@ impl DbModel for Directory {
     ) -> Result<Box<Self>, sqlx::Error> {
         let directory = sqlx::query_as!(
             Directory,
-            "SELECT directory_id, owner_id, parent_id FROM directories WHERE directory_id = $1",
+            "SELECT directory_id, owner_id, parent_id, mount_point, fs_type, group_id, mode, scan_id, raw_path FROM directories WHERE directory_id = $1",
             self.directory_id
         )
         .fetch_one(pool)
@@ -187,22 +711,22 @@ impl DbModel for Directory {
     ) -> Result<Vec<Box<Self>>, sqlx::Error> {
         let directories = sqlx::query_as!(
             Directory,
-            "SELECT directory_id, owner_id, parent_id FROM directories"
+            "SELECT directory_id, owner_id, parent_id, mount_point, fs_type, group_id, mode, scan_id, raw_path FROM directories"
         )
         .fetch_all(pool)
         .await?;
-        Ok(directories.into_iter().map(|d| Box::new(d)).collect())
+        Ok(directories.into_iter().map(Box::new).collect())
     }
 
     async fn select_where(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
-        where_clause: &str,
+        filter: &db::filter::Filter,
     ) -> Result<Vec<Box<Directory>>, sqlx::Error> {
-        let query_string = format!(
-            "SELECT directory_id, owner_id, parent_id FROM directories WHERE {}",
-            where_clause
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT directory_id, owner_id, parent_id, mount_point, fs_type, group_id, mode, scan_id, raw_path FROM directories WHERE ",
         );
-        let rows = sqlx::query(&query_string).fetch_all(pool).await?;
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
 
         let directories: Vec<Box<Directory>> = rows
             .into_iter()
@@ -211,6 +735,12 @@ impl DbModel for Directory {
                     directory_id: row.get("directory_id"),
                     owner_id: row.get("owner_id"),
                     parent_id: row.get("parent_id"),
+                    mount_point: row.get("mount_point"),
+                    fs_type: row.get("fs_type"),
+                    group_id: row.get("group_id"),
+                    mode: row.get("mode"),
+                    scan_id: row.get("scan_id"),
+                    raw_path: row.get("raw_path"),
                 })
             })
             .collect();
@@ -218,6 +748,48 @@ impl DbModel for Directory {
         Ok(directories)
     }
 
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<Directory>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT directory_id, owner_id, parent_id, mount_point, fs_type, group_id, mode, scan_id, raw_path FROM directories WHERE ",
+        );
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Directory],
+    ) -> Result<u64, sqlx::Error> {
+        write_directories(pool, rows, "ON CONFLICT (directory_id) DO NOTHING").await
+    }
+
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Directory],
+    ) -> Result<u64, sqlx::Error> {
+        write_directories(
+            pool,
+            rows,
+            r#"
+            ON CONFLICT (directory_id) DO UPDATE
+            SET owner_id = EXCLUDED.owner_id, parent_id = EXCLUDED.parent_id,
+                mount_point = EXCLUDED.mount_point, fs_type = EXCLUDED.fs_type,
+                group_id = EXCLUDED.group_id, mode = EXCLUDED.mode,
+                scan_id = EXCLUDED.scan_id, raw_path = EXCLUDED.raw_path,
+                -- Recorded totals are stale until a scan finishes the subtree again
+                direct_files = NULL, direct_subdirectories = NULL, direct_bytes = NULL,
+                direct_allocated_bytes = NULL, total_files = NULL, total_subdirectories = NULL,
+                total_bytes = NULL, total_allocated_bytes = NULL
+            "#,
+        )
+        .await
+    }
+
     async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
         let count = sqlx::query("SELECT COUNT(*) FROM directories")
             .fetch_one(pool)
@@ -226,17 +798,97 @@ impl DbModel for Directory {
     }
 }
 
+/// Write files in a single multi-row `UNNEST` statement.
+///
+/// * `pool` - The database connection pool.
+/// * `files` - The rows to write.
+/// * `on_conflict` - The `ON CONFLICT` clause deciding what happens to rows that already exist.
+///
+/// Returns the number of rows affected.
+async fn write_files(
+    pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    files: &[File],
+    on_conflict: &str,
+) -> Result<u64, sqlx::Error> {
+    let file_ids: Vec<String> = files.iter().map(|f| f.file_id.clone()).collect();
+    let names: Vec<String> = files.iter().map(|f| f.name.clone()).collect();
+    let sizes: Vec<i64> = files.iter().map(|f| f.size).collect();
+    let allocated_sizes: Vec<Option<i64>> = files.iter().map(|f| f.allocated_size).collect();
+    let owner_ids: Vec<Option<i32>> = files.iter().map(|f| f.owner_id).collect();
+    let group_ids: Vec<Option<i32>> = files.iter().map(|f| f.group_id).collect();
+    let modes: Vec<Option<i32>> = files.iter().map(|f| f.mode).collect();
+    let directory_ids: Vec<String> = files.iter().map(|f| f.directory_id.clone()).collect();
+    let last_modified: Vec<Option<chrono::NaiveDateTime>> =
+        files.iter().map(|f| f.last_modified).collect();
+    let devices: Vec<Option<i64>> = files.iter().map(|f| f.device).collect();
+    let inodes: Vec<Option<i64>> = files.iter().map(|f| f.inode).collect();
+    let nlinks: Vec<Option<i64>> = files.iter().map(|f| f.nlink).collect();
+    let atimes: Vec<Option<i64>> = files.iter().map(|f| f.atime_ns).collect();
+    let mtimes: Vec<Option<i64>> = files.iter().map(|f| f.mtime_ns).collect();
+    let ctimes: Vec<Option<i64>> = files.iter().map(|f| f.ctime_ns).collect();
+    let btimes: Vec<Option<i64>> = files.iter().map(|f| f.btime_ns).collect();
+    let scan_ids: Vec<Option<i32>> = files.iter().map(|f| f.scan_id).collect();
+    let raw_paths: Vec<Vec<u8>> = files.iter().map(|f| f.raw_path.clone()).collect();
+
+    let query = format!(
+        r#"
+        INSERT INTO files (file_id, name, size, allocated_size, owner_id, group_id, mode, directory_id, last_modified,
+            device, inode, nlink, atime_ns, mtime_ns, ctime_ns, btime_ns, scan_id, raw_path)
+        SELECT * FROM UNNEST(
+            $1::TEXT[], $2::TEXT[], $3::BIGINT[], $4::BIGINT[], $5::INT[], $6::INT[], $7::INT[], $8::TEXT[],
+            $9::TIMESTAMP[], $10::BIGINT[], $11::BIGINT[], $12::BIGINT[], $13::BIGINT[], $14::BIGINT[],
+            $15::BIGINT[], $16::BIGINT[], $17::INT[], $18::BYTEA[]
+        )
+        {on_conflict}
+        "#
+    );
+    let result = sqlx::query(&query)
+        .bind(file_ids)
+        .bind(names)
+        .bind(sizes)
+        .bind(allocated_sizes)
+        .bind(owner_ids)
+        .bind(group_ids)
+        .bind(modes)
+        .bind(directory_ids)
+        .bind(last_modified)
+        .bind(devices)
+        .bind(inodes)
+        .bind(nlinks)
+        .bind(atimes)
+        .bind(mtimes)
+        .bind(ctimes)
+        .bind(btimes)
+        .bind(scan_ids)
+        .bind(raw_paths)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 #[async_trait::async_trait]
 impl DbModel for File {
     async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
         sqlx::query!(
-            "INSERT INTO files (file_id, name, size, owner_id, directory_id, last_modified) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (file_id) DO UPDATE SET name = $2, size = $3, owner_id = $4, directory_id = $5",
+            "INSERT INTO files (file_id, name, size, owner_id, directory_id, last_modified, device, inode, nlink, scan_id, allocated_size, atime_ns, mtime_ns, ctime_ns, btime_ns, group_id, mode, raw_path) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18) ON CONFLICT (file_id) DO UPDATE SET name = $2, size = $3, owner_id = $4, directory_id = $5, last_modified = $6, device = $7, inode = $8, nlink = $9, scan_id = $10, allocated_size = $11, atime_ns = $12, mtime_ns = $13, ctime_ns = $14, btime_ns = $15, group_id = $16, mode = $17, raw_path = $18",
             self.file_id,
             self.name,
             self.size,
             self.owner_id,
             self.directory_id,
-            self.last_modified
+            self.last_modified,
+            self.device,
+            self.inode,
+            self.nlink,
+            self.scan_id,
+            self.allocated_size,
+            self.atime_ns,
+            self.mtime_ns,
+            self.ctime_ns,
+            self.btime_ns,
+            self.group_id,
+            self.mode,
+            self.raw_path
         )
         .execute(pool)
         .await?;
@@ -245,12 +897,25 @@ impl DbModel for File {
 
     async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
         sqlx::query!(
-            "UPDATE files SET name = $2, size = $3, owner_id = $4, directory_id = $5 WHERE file_id = $1",
+            "UPDATE files SET name = $2, size = $3, owner_id = $4, directory_id = $5, last_modified = $6, device = $7, inode = $8, nlink = $9, scan_id = $10, allocated_size = $11, atime_ns = $12, mtime_ns = $13, ctime_ns = $14, btime_ns = $15, group_id = $16, mode = $17, raw_path = $18 WHERE file_id = $1",
             self.file_id,
             self.name,
             self.size,
             self.owner_id,
-            self.directory_id
+            self.directory_id,
+            self.last_modified,
+            self.device,
+            self.inode,
+            self.nlink,
+            self.scan_id,
+            self.allocated_size,
+            self.atime_ns,
+            self.mtime_ns,
+            self.ctime_ns,
+            self.btime_ns,
+            self.group_id,
+            self.mode,
+            self.raw_path
         )
         .execute(pool)
         .await?;
@@ -270,7 +935,7 @@ impl DbModel for File {
     ) -> Result<Box<Self>, sqlx::Error> {
         let file = sqlx::query_as!(
             File,
-            "SELECT file_id, name, size, owner_id, directory_id, last_modified FROM files WHERE file_id = $1",
+            "SELECT file_id, name, size, allocated_size, owner_id, group_id, mode, directory_id, last_modified, device, inode, nlink, atime_ns, mtime_ns, ctime_ns, btime_ns, scan_id, raw_path FROM files WHERE file_id = $1",
             self.file_id
         )
         .fetch_one(pool)
@@ -283,22 +948,22 @@ impl DbModel for File {
     ) -> Result<Vec<Box<Self>>, sqlx::Error> {
         let files = sqlx::query_as!(
             File,
-            "SELECT file_id, name, size, owner_id, directory_id, last_modified FROM files"
+            "SELECT file_id, name, size, allocated_size, owner_id, group_id, mode, directory_id, last_modified, device, inode, nlink, atime_ns, mtime_ns, ctime_ns, btime_ns, scan_id, raw_path FROM files"
         )
         .fetch_all(pool)
         .await?;
-        Ok(files.into_iter().map(|f| Box::new(f)).collect())
+        Ok(files.into_iter().map(Box::new).collect())
     }
 
     async fn select_where(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
-        where_clause: &str,
+        filter: &db::filter::Filter,
     ) -> Result<Vec<Box<File>>, sqlx::Error> {
-        let query_string = format!(
-            "SELECT file_id, name, size, owner_id, directory_id FROM files WHERE {}",
-            where_clause
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT file_id, name, size, allocated_size, owner_id, group_id, mode, directory_id, last_modified, device, inode, nlink, atime_ns, mtime_ns, ctime_ns, btime_ns, scan_id, raw_path FROM files WHERE ",
         );
-        let rows = sqlx::query(&query_string).fetch_all(pool).await?;
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
 
         let files: Vec<Box<File>> = rows
             .into_iter()
@@ -307,9 +972,21 @@ impl DbModel for File {
                     file_id: row.get("file_id"),
                     name: row.get("name"),
                     size: row.get("size"),
+                    allocated_size: row.get("allocated_size"),
                     owner_id: row.get("owner_id"),
+                    group_id: row.get("group_id"),
+                    mode: row.get("mode"),
                     directory_id: row.get("directory_id"),
                     last_modified: row.get("last_modified"),
+                    device: row.get("device"),
+                    inode: row.get("inode"),
+                    nlink: row.get("nlink"),
+                    atime_ns: row.get("atime_ns"),
+                    mtime_ns: row.get("mtime_ns"),
+                    ctime_ns: row.get("ctime_ns"),
+                    btime_ns: row.get("btime_ns"),
+                    scan_id: row.get("scan_id"),
+                    raw_path: row.get("raw_path"),
                 })
             })
             .collect();
@@ -317,6 +994,46 @@ impl DbModel for File {
         Ok(files)
     }
 
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<File>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT file_id, name, size, allocated_size, owner_id, group_id, mode, directory_id, last_modified, device, inode, nlink, atime_ns, mtime_ns, ctime_ns, btime_ns, scan_id, raw_path FROM files WHERE ",
+        );
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[File],
+    ) -> Result<u64, sqlx::Error> {
+        write_files(pool, rows, "ON CONFLICT (file_id) DO NOTHING").await
+    }
+
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[File],
+    ) -> Result<u64, sqlx::Error> {
+        write_files(
+            pool,
+            rows,
+            r#"
+            ON CONFLICT (file_id) DO UPDATE
+            SET name = EXCLUDED.name, size = EXCLUDED.size, allocated_size = EXCLUDED.allocated_size,
+                owner_id = EXCLUDED.owner_id, group_id = EXCLUDED.group_id, mode = EXCLUDED.mode,
+                directory_id = EXCLUDED.directory_id, last_modified = EXCLUDED.last_modified,
+                device = EXCLUDED.device, inode = EXCLUDED.inode, nlink = EXCLUDED.nlink,
+                atime_ns = EXCLUDED.atime_ns, mtime_ns = EXCLUDED.mtime_ns, ctime_ns = EXCLUDED.ctime_ns,
+                btime_ns = EXCLUDED.btime_ns, scan_id = EXCLUDED.scan_id, raw_path = EXCLUDED.raw_path
+            "#,
+        )
+        .await
+    }
+
     async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
         let count = sqlx::query("SELECT COUNT(*) FROM files")
             .fetch_one(pool)
@@ -325,20 +1042,548 @@ impl DbModel for File {
     }
 }
 
+/// Write symbolic links in a single multi-row `UNNEST` statement.
+///
+/// * `pool` - The database connection pool.
+/// * `symlinks` - The rows to write.
+/// * `on_conflict` - The `ON CONFLICT` clause deciding what happens to rows that already exist.
+///
+/// Returns the number of rows affected.
+async fn write_symlinks(
+    pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    symlinks: &[Symlink],
+    on_conflict: &str,
+) -> Result<u64, sqlx::Error> {
+    let symlink_ids: Vec<String> = symlinks.iter().map(|l| l.symlink_id.clone()).collect();
+    let names: Vec<String> = symlinks.iter().map(|l| l.name.clone()).collect();
+    let targets: Vec<String> = symlinks.iter().map(|l| l.target.clone()).collect();
+    let dangling: Vec<bool> = symlinks.iter().map(|l| l.dangling).collect();
+    let sizes: Vec<i64> = symlinks.iter().map(|l| l.size).collect();
+    let owner_ids: Vec<Option<i32>> = symlinks.iter().map(|l| l.owner_id).collect();
+    let directory_ids: Vec<String> = symlinks.iter().map(|l| l.directory_id.clone()).collect();
+    let scan_ids: Vec<Option<i32>> = symlinks.iter().map(|l| l.scan_id).collect();
+    let raw_paths: Vec<Vec<u8>> = symlinks.iter().map(|l| l.raw_path.clone()).collect();
+    let raw_targets: Vec<Vec<u8>> = symlinks.iter().map(|l| l.raw_target.clone()).collect();
+
+    let query = format!(
+        r#"
+        INSERT INTO symlinks (symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id,
+            raw_path, raw_target)
+        SELECT * FROM UNNEST(
+            $1::TEXT[], $2::TEXT[], $3::TEXT[], $4::BOOLEAN[], $5::BIGINT[], $6::INT[], $7::TEXT[], $8::INT[],
+            $9::BYTEA[], $10::BYTEA[]
+        )
+        {on_conflict}
+        "#
+    );
+    let result = sqlx::query(&query)
+        .bind(symlink_ids)
+        .bind(names)
+        .bind(targets)
+        .bind(dangling)
+        .bind(sizes)
+        .bind(owner_ids)
+        .bind(directory_ids)
+        .bind(scan_ids)
+        .bind(raw_paths)
+        .bind(raw_targets)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[async_trait::async_trait]
+impl DbModel for Symlink {
+    async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO symlinks (symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id, raw_path, raw_target) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT (symlink_id) DO UPDATE SET name = $2, target = $3, dangling = $4, size = $5, owner_id = $6, directory_id = $7, scan_id = $8, raw_path = $9, raw_target = $10",
+            self.symlink_id,
+            self.name,
+            self.target,
+            self.dangling,
+            self.size,
+            self.owner_id,
+            self.directory_id,
+            self.scan_id,
+            self.raw_path,
+            self.raw_target
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE symlinks SET name = $2, target = $3, dangling = $4, size = $5, owner_id = $6, directory_id = $7, scan_id = $8, raw_path = $9, raw_target = $10 WHERE symlink_id = $1",
+            self.symlink_id,
+            self.name,
+            self.target,
+            self.dangling,
+            self.size,
+            self.owner_id,
+            self.directory_id,
+            self.scan_id,
+            self.raw_path,
+            self.raw_target
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM symlinks WHERE symlink_id = $1",
+            self.symlink_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn select(
+        &self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Box<Self>, sqlx::Error> {
+        let symlink = sqlx::query_as!(
+            Symlink,
+            "SELECT symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id, raw_path, raw_target FROM symlinks WHERE symlink_id = $1",
+            self.symlink_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Box::new(symlink))
+    }
+
+    async fn select_all(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Vec<Box<Self>>, sqlx::Error> {
+        let symlinks = sqlx::query_as!(
+            Symlink,
+            "SELECT symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id, raw_path, raw_target FROM symlinks"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(symlinks.into_iter().map(Box::new).collect())
+    }
+
+    async fn select_where(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> Result<Vec<Box<Symlink>>, sqlx::Error> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id, raw_path, raw_target FROM symlinks WHERE ",
+        );
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
+
+        let symlinks: Vec<Box<Symlink>> = rows
+            .into_iter()
+            .map(|row| {
+                Box::new(Symlink {
+                    symlink_id: row.get("symlink_id"),
+                    name: row.get("name"),
+                    target: row.get("target"),
+                    dangling: row.get("dangling"),
+                    size: row.get("size"),
+                    owner_id: row.get("owner_id"),
+                    directory_id: row.get("directory_id"),
+                    scan_id: row.get("scan_id"),
+                    raw_path: row.get("raw_path"),
+                    raw_target: row.get("raw_target"),
+                })
+            })
+            .collect();
+
+        Ok(symlinks)
+    }
+
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<Symlink>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT symlink_id, name, target, dangling, size, owner_id, directory_id, scan_id, raw_path, raw_target FROM symlinks WHERE ",
+        );
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn insert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Symlink],
+    ) -> Result<u64, sqlx::Error> {
+        write_symlinks(pool, rows, "ON CONFLICT (symlink_id) DO NOTHING").await
+    }
+
+    async fn upsert_many(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        rows: &[Symlink],
+    ) -> Result<u64, sqlx::Error> {
+        write_symlinks(
+            pool,
+            rows,
+            r#"
+            ON CONFLICT (symlink_id) DO UPDATE
+            SET name = EXCLUDED.name, target = EXCLUDED.target, dangling = EXCLUDED.dangling,
+                size = EXCLUDED.size, owner_id = EXCLUDED.owner_id,
+                directory_id = EXCLUDED.directory_id, scan_id = EXCLUDED.scan_id,
+                raw_path = EXCLUDED.raw_path, raw_target = EXCLUDED.raw_target
+            "#,
+        )
+        .await
+    }
+
+    async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query("SELECT COUNT(*) FROM symlinks")
+            .fetch_one(pool)
+            .await?;
+        Ok(count.get("count"))
+    }
+}
+
+#[async_trait::async_trait]
+impl DbModel for ScanError {
+    /// Insert the error. The error id is assigned by the database.
+    async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO scan_errors (scan_id, path, error_kind, errno, phase, message, occurred_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            self.scan_id,
+            self.path,
+            self.error_kind,
+            self.errno,
+            self.phase,
+            self.message,
+            self.occurred_at
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE scan_errors SET scan_id = $2, path = $3, error_kind = $4, errno = $5, phase = $6, message = $7, occurred_at = $8 WHERE error_id = $1",
+            self.error_id,
+            self.scan_id,
+            self.path,
+            self.error_kind,
+            self.errno,
+            self.phase,
+            self.message,
+            self.occurred_at
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM scan_errors WHERE error_id = $1", self.error_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn select(
+        &self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Box<Self>, sqlx::Error> {
+        let error = sqlx::query_as!(
+            ScanError,
+            "SELECT error_id, scan_id, path, error_kind, errno, phase, message, occurred_at FROM scan_errors WHERE error_id = $1",
+            self.error_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Box::new(error))
+    }
+
+    async fn select_all(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Vec<Box<Self>>, sqlx::Error> {
+        let errors = sqlx::query_as!(
+            ScanError,
+            "SELECT error_id, scan_id, path, error_kind, errno, phase, message, occurred_at FROM scan_errors"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(errors.into_iter().map(Box::new).collect())
+    }
+
+    async fn select_where(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> Result<Vec<Box<ScanError>>, sqlx::Error> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT error_id, scan_id, path, error_kind, errno, phase, message, occurred_at FROM scan_errors WHERE ",
+        );
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
+
+        let errors: Vec<Box<ScanError>> = rows
+            .into_iter()
+            .map(|row| {
+                Box::new(ScanError {
+                    error_id: row.get("error_id"),
+                    scan_id: row.get("scan_id"),
+                    path: row.get("path"),
+                    error_kind: row.get("error_kind"),
+                    errno: row.get("errno"),
+                    phase: row.get("phase"),
+                    message: row.get("message"),
+                    occurred_at: row.get("occurred_at"),
+                })
+            })
+            .collect();
+
+        Ok(errors)
+    }
+
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<ScanError>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT error_id, scan_id, path, error_kind, errno, phase, message, occurred_at FROM scan_errors WHERE ",
+        );
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query("SELECT COUNT(*) FROM scan_errors")
+            .fetch_one(pool)
+            .await?;
+        Ok(count.get("count"))
+    }
+}
+
+#[async_trait::async_trait]
+impl DbModel for Scan {
+    async fn insert(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO scans (scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT (scan_id) DO UPDATE SET root = $2, host = $3, started_at = $4, finished_at = $5, status = $6, file_count = $7, directory_count = $8, total_bytes = $9, exclude_rules = $10",
+            self.scan_id,
+            self.root,
+            self.host,
+            self.started_at,
+            self.finished_at,
+            self.status,
+            self.file_count,
+            self.directory_count,
+            self.total_bytes,
+            &self.exclude_rules
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE scans SET root = $2, host = $3, started_at = $4, finished_at = $5, status = $6, file_count = $7, directory_count = $8, total_bytes = $9, exclude_rules = $10 WHERE scan_id = $1",
+            self.scan_id,
+            self.root,
+            self.host,
+            self.started_at,
+            self.finished_at,
+            self.status,
+            self.file_count,
+            self.directory_count,
+            self.total_bytes,
+            &self.exclude_rules
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM scans WHERE scan_id = $1", self.scan_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn select(
+        &self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Box<Self>, sqlx::Error> {
+        let scan = sqlx::query_as!(
+            Scan,
+            "SELECT scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules FROM scans WHERE scan_id = $1",
+            self.scan_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Box::new(scan))
+    }
+
+    async fn select_all(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+    ) -> Result<Vec<Box<Self>>, sqlx::Error> {
+        let scans = sqlx::query_as!(
+            Scan,
+            "SELECT scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules FROM scans"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(scans.into_iter().map(Box::new).collect())
+    }
+
+    async fn select_where(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> Result<Vec<Box<Scan>>, sqlx::Error> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules FROM scans WHERE ",
+        );
+        filter.push_to(&mut query);
+        let rows = query.build().fetch_all(pool).await?;
+
+        let scans: Vec<Box<Scan>> = rows
+            .into_iter()
+            .map(|row| {
+                Box::new(Scan {
+                    scan_id: row.get("scan_id"),
+                    root: row.get("root"),
+                    host: row.get("host"),
+                    started_at: row.get("started_at"),
+                    finished_at: row.get("finished_at"),
+                    status: row.get("status"),
+                    file_count: row.get("file_count"),
+                    directory_count: row.get("directory_count"),
+                    total_bytes: row.get("total_bytes"),
+                    exclude_rules: row.get("exclude_rules"),
+                })
+            })
+            .collect();
+
+        Ok(scans)
+    }
+
+    fn select_where_stream(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        filter: &db::filter::Filter,
+    ) -> BoxStream<'static, Result<Box<Scan>, sqlx::Error>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules FROM scans WHERE ",
+        );
+        filter.push_to(&mut query);
+        db::stream::fetch(pool, query)
+            .map(|row| row.map(Box::new))
+            .boxed()
+    }
+
+    async fn count_all(pool: &sqlx::Pool<sqlx::postgres::Postgres>) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query("SELECT COUNT(*) FROM scans")
+            .fetch_one(pool)
+            .await?;
+        Ok(count.get("count"))
+    }
+}
+
+impl Scan {
+    /// Record the start of a new scan. The scan id is assigned by the database.
+    ///
+    /// * `pool` - The database connection pool.
+    /// * `root` - The root directory being scanned.
+    /// * `host` - The host the crawler runs on.
+    /// * `exclude_rules` - The rules used to skip subtrees during the scan.
+    ///
+    /// Returns the newly created scan.
+    pub async fn start(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        root: &str,
+        host: Option<String>,
+        exclude_rules: &[String],
+    ) -> Result<Scan, sqlx::Error> {
+        let scan = sqlx::query_as!(
+            Scan,
+            "INSERT INTO scans (root, host, started_at, status, exclude_rules) VALUES ($1, $2, $3, $4, $5) RETURNING scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules",
+            root,
+            host,
+            chrono::Utc::now().naive_utc(),
+            ScanStatus::Running.as_str(),
+            exclude_rules
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(scan)
+    }
+
+    /// Record the end of the scan with its final status and totals.
+    ///
+    /// * `pool` - The database connection pool.
+    /// * `status` - The final status of the scan.
+    pub async fn finish(
+        &mut self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        status: ScanStatus,
+    ) -> Result<(), sqlx::Error> {
+        self.finished_at = Some(chrono::Utc::now().naive_utc());
+        self.status = status.as_str().to_string();
+        self.update(pool).await
+    }
+
+    /// Find the most recent scan of the root if it did not complete, so that it can be resumed.
+    /// Older unfinished scans are superseded by any newer scan, and are never resumed.
+    ///
+    /// * `pool` - The database connection pool.
+    /// * `root` - The root directory being scanned.
+    ///
+    /// Returns the scan, or None if there is none or the most recent one completed.
+    pub async fn latest_unfinished(
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        root: &str,
+    ) -> Result<Option<Scan>, sqlx::Error> {
+        let scan = sqlx::query_as!(
+            Scan,
+            "SELECT scan_id, root, host, started_at, finished_at, status, file_count, directory_count, total_bytes, exclude_rules FROM scans WHERE root = $1 ORDER BY scan_id DESC LIMIT 1",
+            root
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(scan.filter(|scan| scan.status != ScanStatus::Completed.as_str()))
+    }
+
+    /// Mark an unfinished scan as running again.
+    ///
+    /// * `pool` - The database connection pool.
+    /// * `host` - The host the crawler now runs on.
+    /// * `exclude_rules` - The rules used to skip subtrees from now on.
+    pub async fn reopen(
+        &mut self,
+        pool: &sqlx::Pool<sqlx::postgres::Postgres>,
+        host: Option<String>,
+        exclude_rules: &[String],
+    ) -> Result<(), sqlx::Error> {
+        self.host = host;
+        self.finished_at = None;
+        self.status = ScanStatus::Running.as_str().to_string();
+        self.exclude_rules = exclude_rules.to_vec();
+        self.update(pool).await
+    }
+}
+
 #[async_trait::async_trait]
 impl DbEstimateRow for File {
     async fn estimate_count(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
     ) -> Result<i64, sqlx::Error> {
-        let query = format!(
-            r#"
+        let query = r#"
             SELECT reltuples AS estimated_rows
             FROM pg_class
             WHERE relname = 'files';
-            "#
-        );
+            "#;
 
-        let row = sqlx::query(&query).fetch_one(pool).await?;
+        let row = sqlx::query(query).fetch_one(pool).await?;
         let estimated_rows: i64 = row.get("estimated_rows");
         Ok(estimated_rows)
     }
@@ -349,15 +1594,13 @@ impl DbEstimateRow for Directory {
     async fn estimate_count(
         pool: &sqlx::Pool<sqlx::postgres::Postgres>,
     ) -> Result<i64, sqlx::Error> {
-        let query = format!(
-            r#"
+        let query = r#"
             SELECT reltuples AS estimated_rows
             FROM pg_class
             WHERE relname = 'directories';
-            "#
-        );
+            "#;
 
-        let row = sqlx::query(&query).fetch_one(pool).await?;
+        let row = sqlx::query(query).fetch_one(pool).await?;
         let estimated_rows: i64 = row.get("estimated_rows");
         Ok(estimated_rows)
     }
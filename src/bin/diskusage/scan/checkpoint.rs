@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use sqlx::Row;
+
+use crate::models::definitions::{Checkpoint, ErrorPhase, Rollup};
+use crate::writer;
+
+/// Whether a finished directory is checkpointed, i.e. whether it sits directly below the root.
+/// Deeper subtrees are covered by the checkpoint of the top-level subtree containing them.
+///
+/// * `root` - The root directory of the scan.
+/// * `directory` - The directory whose subtree was finished.
+pub fn is_checkpointed(root: &Path, directory: &Path) -> bool {
+    directory != root && directory.parent() == Some(root)
+}
+
+/// Build the checkpoint of a finished top-level subtree. Only subtrees that were read in full
+/// are checkpointed, otherwise resuming would skip the parts that could not be read.
+///
+/// * `scan_id` - The id of the current scan.
+/// * `directory` - The directory at the top of the subtree.
+pub fn checkpoint(scan_id: i32, directory: &Path) -> Checkpoint {
+    Checkpoint {
+        scan_id,
+        path: db::paths::encode(directory),
+        completed_at: chrono::Utc::now().naive_utc(),
+    }
+}
+
+/// The top-level subtrees an earlier run of the scan finished, with the totals it recorded,
+/// keyed by their encoded path.
+#[derive(Debug, Default)]
+pub struct Completed {
+    subtrees: HashMap<String, Rollup>,
+}
+
+impl Completed {
+    /// Load the finished subtrees of a scan.
+    /// Subtrees whose totals were not written, or with walk errors at or below their top, are
+    /// left out, so they are walked again.
+    ///
+    /// * `pool` - The database connection pool.
+    /// * `scan_id` - The id of the scan being resumed.
+    pub async fn load(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        scan_id: i32,
+    ) -> Result<Completed, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT d.directory_id, d.direct_files, d.direct_subdirectories, d.direct_bytes,
+                d.direct_allocated_bytes, d.total_files, d.total_subdirectories, d.total_bytes,
+                d.total_allocated_bytes
+            FROM scan_checkpoints c
+            JOIN directories d ON d.directory_id = c.path
+            WHERE c.scan_id = $1 AND d.scan_id = $1 AND d.total_bytes IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM scan_errors e
+                WHERE e.scan_id = $1 AND e.phase = $2
+                AND (e.path = c.path OR left(e.path, length(c.path) + 1) = c.path || '/')
+            )
+            "#,
+        )
+        .bind(scan_id)
+        .bind(ErrorPhase::Walk.as_str())
+        .fetch_all(pool)
+        .await?;
+
+        let subtrees = rows
+            .into_iter()
+            .map(|row| {
+                let rollup = Rollup {
+                    directory_id: row.get("directory_id"),
+                    direct_files: row.get("direct_files"),
+                    direct_subdirectories: row.get("direct_subdirectories"),
+                    direct_bytes: row.get("direct_bytes"),
+                    direct_allocated_bytes: row.get("direct_allocated_bytes"),
+                    total_files: row.get("total_files"),
+                    total_subdirectories: row.get("total_subdirectories"),
+                    total_bytes: row.get("total_bytes"),
+                    total_allocated_bytes: row.get("total_allocated_bytes"),
+                };
+                (rollup.directory_id.clone(), rollup)
+            })
+            .collect();
+        Ok(Completed { subtrees })
+    }
+
+    /// The number of finished subtrees.
+    pub fn len(&self) -> usize {
+        self.subtrees.len()
+    }
+
+    /// The recorded totals of a subtree, if it was finished.
+    ///
+    /// * `directory` - The directory at the top of the subtree.
+    pub fn get(&self, directory: &Path) -> Option<&Rollup> {
+        self.subtrees.get(&db::paths::encode(directory))
+    }
+
+    /// Count the finished subtrees in the totals of the scan, since this run does not write them.
+    ///
+    /// * `stats` - The statistics collected by the writer.
+    pub fn add_to(&self, stats: &mut writer::batch::Stats) {
+        for rollup in self.subtrees.values() {
+            stats.directories += rollup.total_subdirectories.max(0) as u64 + 1;
+            stats.files += rollup.total_files.max(0) as u64;
+            stats.bytes += rollup.total_bytes.max(0) as u64;
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod checkpoint;
+pub mod reconcile;
+pub mod rollup;
+pub mod session;
+pub mod shutdown;
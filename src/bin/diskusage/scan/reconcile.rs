@@ -0,0 +1,140 @@
+use sqlx::Row;
+
+use crate::models::definitions::{ErrorPhase, Scan};
+use crate::scan::rollup;
+
+/// The rows removed by a reconciliation pass.
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub files: i64,
+    pub symlinks: i64,
+    pub directories: i64,
+    pub bytes: i64,
+}
+
+/// Remove the files and directories under the scan's root that were not seen by the scan.
+/// This must only run after the scan completed successfully, otherwise rows that were simply
+/// not reached yet would be removed. Rows below directories the scan failed to read are kept,
+/// since they may still exist on disk, and so are rows the scan failed to write, which still
+/// carry the id of an earlier scan.
+///
+/// * `pool` - The database connection pool.
+/// * `scan` - The completed scan.
+///
+/// Returns a summary of the removed rows.
+pub async fn remove_unseen(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    scan: &Scan,
+) -> Result<Summary, sqlx::Error> {
+    let root = scan.root.trim_end_matches('/');
+    let subtree_pattern = format!("{}/%", db::filter::escape_like(root));
+
+    let mut tx = pool.begin().await?;
+
+    let failed: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT path FROM scan_errors WHERE scan_id = $1 AND phase = ANY($2)",
+    )
+    .bind(scan.scan_id)
+    .bind(vec![ErrorPhase::Walk.as_str(), ErrorPhase::Insert.as_str()])
+    .fetch_all(&mut *tx)
+    .await?;
+    let failed_subtrees: Vec<String> = failed
+        .iter()
+        .map(|path| format!("{}/%", db::filter::escape_like(path.trim_end_matches('/'))))
+        .collect();
+
+    let removed_files = sqlx::query(
+        r#"
+        WITH removed AS (
+            DELETE FROM files f
+            WHERE f.scan_id IS DISTINCT FROM $1
+            AND (f.directory_id = $2 OR f.directory_id = $3
+                OR f.directory_id LIKE $4 ESCAPE '\')
+            AND NOT (f.file_id = ANY($5) OR f.directory_id = ANY($5))
+            AND NOT EXISTS (
+                SELECT 1 FROM UNNEST($6::TEXT[]) p WHERE f.directory_id LIKE p ESCAPE '\'
+            )
+            RETURNING f.size
+        )
+        SELECT COUNT(*) AS files, COALESCE(SUM(size), 0)::BIGINT AS bytes FROM removed
+        "#,
+    )
+    .bind(scan.scan_id)
+    .bind(&scan.root)
+    .bind(root)
+    .bind(&subtree_pattern)
+    .bind(&failed)
+    .bind(&failed_subtrees)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let removed_symlinks = sqlx::query(
+        r#"
+        WITH removed AS (
+            DELETE FROM symlinks l
+            WHERE l.scan_id IS DISTINCT FROM $1
+            AND (l.directory_id = $2 OR l.directory_id = $3
+                OR l.directory_id LIKE $4 ESCAPE '\')
+            AND NOT (l.symlink_id = ANY($5) OR l.directory_id = ANY($5))
+            AND NOT EXISTS (
+                SELECT 1 FROM UNNEST($6::TEXT[]) p WHERE l.directory_id LIKE p ESCAPE '\'
+            )
+            RETURNING l.symlink_id
+        )
+        SELECT COUNT(*) AS symlinks FROM removed
+        "#,
+    )
+    .bind(scan.scan_id)
+    .bind(&scan.root)
+    .bind(root)
+    .bind(&subtree_pattern)
+    .bind(&failed)
+    .bind(&failed_subtrees)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let removed_directories = sqlx::query(
+        r#"
+        WITH removed AS (
+            DELETE FROM directories d
+            WHERE d.scan_id IS DISTINCT FROM $1
+            AND d.directory_id LIKE $2 ESCAPE '\'
+            AND NOT d.directory_id = ANY($3)
+            AND NOT EXISTS (
+                SELECT 1 FROM UNNEST($4::TEXT[]) p WHERE d.directory_id LIKE p ESCAPE '\'
+            )
+            AND NOT EXISTS (SELECT 1 FROM files f WHERE f.directory_id = d.directory_id)
+            AND NOT EXISTS (SELECT 1 FROM symlinks l WHERE l.directory_id = d.directory_id)
+            RETURNING d.directory_id
+        )
+        SELECT COUNT(*) AS directories FROM removed
+        "#,
+    )
+    .bind(scan.scan_id)
+    .bind(&subtree_pattern)
+    .bind(&failed)
+    .bind(&failed_subtrees)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Directories above the root counted the removed rows in their totals
+    rollup::clear_ancestors(&mut *tx, &scan.root).await?;
+
+    tx.commit().await?;
+
+    let summary = Summary {
+        files: removed_files.get("files"),
+        symlinks: removed_symlinks.get("symlinks"),
+        directories: removed_directories.get("directories"),
+        bytes: removed_files.get("bytes"),
+    };
+    log::info!(
+        "Reconciled scan {}: removed {} files ({} bytes), {} symlinks and {} directories no longer on disk",
+        scan.scan_id,
+        summary.files,
+        summary.bytes,
+        summary.symlinks,
+        summary.directories
+    );
+    Ok(summary)
+}
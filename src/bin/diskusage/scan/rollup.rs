@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use crate::models::definitions::Rollup;
+
+/// Accumulates the roll-up totals of the directories being walked.
+///
+/// Files are counted in their parent directory as they are visited. When the walker reports
+/// that a directory's subtree is finished, its totals are complete: they are returned to be
+/// written, and folded into the parent, which cannot finish before all of its children have.
+/// Only directories still being walked are kept in memory.
+pub struct Rollups {
+    root: PathBuf,
+    open: dashmap::DashMap<PathBuf, Rollup>,
+}
+
+impl Rollups {
+    /// Create the accumulator for a walk of the given root directory.
+    ///
+    /// * `root` - The root directory of the scan. Its totals are not folded into its parent.
+    pub fn new(root: &Path) -> Rollups {
+        Rollups {
+            root: root.to_path_buf(),
+            open: dashmap::DashMap::new(),
+        }
+    }
+
+    /// Count a file in the directory that contains it.
+    ///
+    /// * `directory` - The directory containing the file.
+    /// * `size` - The apparent size of the file in bytes.
+    /// * `allocated_size` - The allocated size of the file in bytes.
+    pub fn add_file(&self, directory: &Path, size: i64, allocated_size: i64) {
+        let mut rollup = self.open.entry(directory.to_path_buf()).or_default();
+        rollup.direct_files += 1;
+        rollup.direct_bytes += size;
+        rollup.direct_allocated_bytes += allocated_size;
+    }
+
+    /// Close a directory whose whole subtree has been walked.
+    ///
+    /// * `directory` - The directory that was finished.
+    ///
+    /// Returns
+    /// The complete totals of the directory.
+    pub fn finish(&self, directory: &Path) -> Rollup {
+        let mut rollup = self
+            .open
+            .remove(directory)
+            .map(|(_, rollup)| rollup)
+            .unwrap_or_default();
+        rollup.directory_id = db::paths::encode(directory);
+        // The subtree totals so far only hold what the subdirectories folded in
+        rollup.total_files += rollup.direct_files;
+        rollup.total_bytes += rollup.direct_bytes;
+        rollup.total_allocated_bytes += rollup.direct_allocated_bytes;
+
+        self.fold_into_parent(directory, &rollup);
+        rollup
+    }
+
+    /// Drop the totals of a directory whose subtree was not fully read. Nothing is folded into
+    /// the parent, which is incomplete as well.
+    ///
+    /// * `directory` - The directory that was finished.
+    pub fn abandon(&self, directory: &Path) {
+        self.open.remove(directory);
+    }
+
+    /// Count a subtree that is not walked, because an earlier run of the scan finished it.
+    ///
+    /// * `directory` - The directory at the top of the subtree.
+    /// * `rollup` - The totals recorded for the subtree by the earlier run.
+    pub fn skip(&self, directory: &Path, rollup: &Rollup) {
+        self.fold_into_parent(directory, rollup);
+    }
+
+    /// Add the totals of a finished directory to those of its parent.
+    fn fold_into_parent(&self, directory: &Path, rollup: &Rollup) {
+        if directory == self.root {
+            return;
+        }
+        if let Some(parent) = directory.parent() {
+            let mut parent_rollup = self.open.entry(parent.to_path_buf()).or_default();
+            parent_rollup.direct_subdirectories += 1;
+            parent_rollup.total_subdirectories += rollup.total_subdirectories + 1;
+            parent_rollup.total_files += rollup.total_files;
+            parent_rollup.total_bytes += rollup.total_bytes;
+            parent_rollup.total_allocated_bytes += rollup.total_allocated_bytes;
+        }
+    }
+}
+
+/// Clear the recorded totals of the directories above a scan's root. Their totals include the
+/// subtree as an earlier scan saw it, so they are stale once this scan writes or removes rows
+/// below the root, until a scan of the ancestor itself finishes again.
+///
+/// * `executor` - The connection or transaction to run the update on.
+/// * `root_dir` - The encoded root directory of the scan.
+///
+/// Returns the number of directories whose totals were cleared.
+pub async fn clear_ancestors<'e, E>(executor: E, root_dir: &str) -> Result<u64, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let ancestors: Vec<String> = Path::new(root_dir)
+        .ancestors()
+        .skip(1)
+        .map(|ancestor| ancestor.to_string_lossy().to_string())
+        .collect();
+    let result = sqlx::query(
+        r#"
+        UPDATE directories SET
+            direct_files = NULL, direct_subdirectories = NULL, direct_bytes = NULL,
+            direct_allocated_bytes = NULL, total_files = NULL, total_subdirectories = NULL,
+            total_bytes = NULL, total_allocated_bytes = NULL
+        WHERE directory_id = ANY($1) AND total_bytes IS NOT NULL
+        "#,
+    )
+    .bind(&ancestors)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,127 @@
+use crate::models::definitions::{Scan, ScanStatus};
+use crate::scan::{checkpoint, rollup};
+use crate::writer;
+
+/// Get the hostname of the machine running the crawler.
+///
+/// Returns
+/// The hostname, or None if it cannot be determined.
+pub fn hostname() -> Option<String> {
+    match std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(hostname) => Some(hostname.trim().to_string()),
+        Err(_) => std::env::var("HOSTNAME").ok(),
+    }
+}
+
+/// Open a new scan session for the given root directory.
+///
+/// * `pool` - The database connection pool.
+/// * `root_dir` - The root directory being scanned.
+/// * `exclude_rules` - The exclusion rules in effect for this scan.
+///
+/// Returns the scan record, whose id is used to tag every row written by this crawl.
+pub async fn begin(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    root_dir: &str,
+    exclude_rules: &[String],
+) -> Result<Scan, sqlx::Error> {
+    let scan = Scan::start(pool, root_dir, hostname(), exclude_rules).await?;
+    log::info!(
+        "Started scan {} of {} on {}",
+        scan.scan_id,
+        scan.root,
+        scan.host.as_deref().unwrap_or("unknown host")
+    );
+    clear_ancestor_totals(pool, root_dir).await?;
+    Ok(scan)
+}
+
+/// Resume the most recent unfinished scan of the root directory, or open a new one if there is none.
+///
+/// * `pool` - The database connection pool.
+/// * `root_dir` - The root directory being scanned.
+/// * `exclude_rules` - The exclusion rules in effect for this run.
+///
+/// Returns the scan record, with the top-level subtrees an earlier run already finished.
+pub async fn resume(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    root_dir: &str,
+    exclude_rules: &[String],
+) -> Result<(Scan, checkpoint::Completed), sqlx::Error> {
+    let mut scan = match Scan::latest_unfinished(pool, root_dir).await? {
+        Some(scan) => scan,
+        None => {
+            log::info!(
+                "No unfinished scan of {} to resume, starting a new one",
+                root_dir
+            );
+            let scan = begin(pool, root_dir, exclude_rules).await?;
+            return Ok((scan, checkpoint::Completed::default()));
+        }
+    };
+    if scan.exclude_rules != exclude_rules {
+        log::warn!(
+            "Scan {} was started with different exclusion rules ({:?}), finished subtrees keep them",
+            scan.scan_id,
+            scan.exclude_rules
+        );
+    }
+    let previous_status = scan.status.clone();
+    scan.reopen(pool, hostname(), exclude_rules).await?;
+    clear_ancestor_totals(pool, root_dir).await?;
+    let completed = checkpoint::Completed::load(pool, scan.scan_id).await?;
+    log::info!(
+        "Resuming scan {} of {} ({}), skipping {} finished subtrees",
+        scan.scan_id,
+        scan.root,
+        previous_status,
+        completed.len()
+    );
+    Ok((scan, completed))
+}
+
+/// Clear the totals of the directories above the root, which no longer match what the scan writes.
+///
+/// * `pool` - The database connection pool.
+/// * `root_dir` - The root directory being scanned.
+async fn clear_ancestor_totals(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    root_dir: &str,
+) -> Result<(), sqlx::Error> {
+    let cleared = rollup::clear_ancestors(pool, root_dir).await?;
+    if cleared > 0 {
+        log::info!(
+            "Cleared the totals of {} directories above {}, until they are scanned again",
+            cleared,
+            root_dir
+        );
+    }
+    Ok(())
+}
+
+/// Close the scan session, recording its final status and totals.
+///
+/// * `pool` - The database connection pool.
+/// * `scan` - The scan to close.
+/// * `stats` - The statistics collected by the writer.
+/// * `status` - The final status of the scan.
+pub async fn end(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    scan: &mut Scan,
+    stats: &writer::batch::Stats,
+    status: ScanStatus,
+) -> Result<(), sqlx::Error> {
+    scan.file_count = stats.files as i64;
+    scan.directory_count = stats.directories as i64;
+    scan.total_bytes = stats.bytes as i64;
+    scan.finish(pool, status).await?;
+    log::info!(
+        "Finished scan {} ({}): {} files, {} directories, {} bytes",
+        scan.scan_id,
+        scan.status,
+        scan.file_count,
+        scan.directory_count,
+        scan.total_bytes
+    );
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Tracks whether the crawler was asked to stop by SIGINT or SIGTERM.
+///
+/// The first signal stops the walk: no new directories are read, the records already queued
+/// are written, and the scan is recorded as interrupted. A second signal exits at once.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    stop: Arc<AtomicBool>,
+    signal: Arc<AtomicI32>,
+}
+
+impl Shutdown {
+    /// Install the signal handlers. Must be called from within the tokio runtime.
+    ///
+    /// Returns
+    /// The shutdown state, updated in the background when a signal arrives.
+    pub fn listen() -> std::io::Result<Shutdown> {
+        let shutdown = Shutdown::default();
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        let state = shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let (name, kind) = tokio::select! {
+                    _ = interrupt.recv() => ("SIGINT", SignalKind::interrupt()),
+                    _ = terminate.recv() => ("SIGTERM", SignalKind::terminate()),
+                };
+                if state.requested() {
+                    log::warn!(
+                        "Received {} again, exiting without waiting for pending writes",
+                        name
+                    );
+                    std::process::exit(128 + kind.as_raw_value());
+                }
+                // The signal is stored first, so that a stopped walk always has an exit code
+                state.signal.store(kind.as_raw_value(), Ordering::SeqCst);
+                state.stop.store(true, Ordering::SeqCst);
+                log::warn!(
+                    "Received {}, stopping the crawl once queued records are written (send it again to exit at once)",
+                    name
+                );
+            }
+        });
+        Ok(shutdown)
+    }
+
+    /// The flag set when the walk should stop.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    /// Whether a signal was received.
+    pub fn requested(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// The exit code to use if a signal was received: 128 plus the signal number, as shells do.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(128 + signal),
+        }
+    }
+
+    /// Exit with the signal's exit code if a signal was received. Called once the records
+    /// queued before the signal have been written and the scan has been recorded.
+    pub fn exit_if_requested(&self) {
+        if let Some(code) = self.exit_code() {
+            log::warn!("Exiting with code {} after the crawl was interrupted", code);
+            std::process::exit(code);
+        }
+    }
+}
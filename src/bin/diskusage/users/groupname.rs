@@ -0,0 +1,10 @@
+/// Get gid to group name mapping
+///
+/// * `gid` - The group ID.
+///
+/// Returns
+/// The name of the group.
+pub fn get_groupname(gid: u32) -> Option<String> {
+    let group = users::get_group_by_gid(gid)?;
+    Some(group.name().to_string_lossy().to_string())
+}
@@ -1 +1,2 @@
+pub mod groupname;
 pub mod username;
@@ -1,4 +1,4 @@
-//// Get uid to username mapping
+/// Get uid to username mapping
 ///
 /// * `uid` - The user ID.
 ///
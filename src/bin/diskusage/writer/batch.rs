@@ -0,0 +1,599 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::models::definitions::{
+    Checkpoint, DbModel, Directory, File, Group, Rollup, ScanError, Symlink, User,
+};
+use crate::users;
+use crate::writer::retry;
+
+/// A record produced by the crawler, to be written to the database by the writer task.
+#[derive(Debug)]
+pub enum Record {
+    Directory(Directory),
+    File(File),
+    Symlink(Symlink),
+    Rollup(Rollup),
+    Error(ScanError),
+    /// Queued once everything below a top-level subtree has been queued.
+    Checkpoint(Checkpoint),
+}
+
+/// Configuration for the batch writer.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The number of records to buffer before flushing.
+    pub batch_size: usize,
+    /// The maximum time a record may wait in the buffer before being flushed.
+    pub flush_interval: std::time::Duration,
+    /// How failed writes are retried.
+    pub retry: retry::Policy,
+}
+
+/// Statistics collected by the writer task.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub batches: u64,
+    pub directories: u64,
+    pub files: u64,
+    pub symlinks: u64,
+    pub errors: u64,
+    pub bytes: u64,
+    pub checkpoints: u64,
+    /// Records that could not be written, even one at a time, and were given up on.
+    pub dead_letters: Vec<ScanError>,
+}
+
+/// Records waiting to be flushed, keyed by their primary key.
+/// Postgres rejects an upsert that touches the same row twice, so duplicates are collapsed here.
+#[derive(Default)]
+struct Batch {
+    directories: HashMap<String, Directory>,
+    files: HashMap<String, File>,
+    symlinks: HashMap<String, Symlink>,
+    rollups: HashMap<String, Rollup>,
+    errors: Vec<ScanError>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Batch {
+    fn push(&mut self, record: Record) {
+        match record {
+            Record::Directory(directory) => {
+                self.directories
+                    .insert(directory.directory_id.clone(), directory);
+            }
+            Record::File(file) => {
+                self.files.insert(file.file_id.clone(), file);
+            }
+            Record::Symlink(symlink) => {
+                self.symlinks.insert(symlink.symlink_id.clone(), symlink);
+            }
+            Record::Rollup(rollup) => {
+                self.rollups.insert(rollup.directory_id.clone(), rollup);
+            }
+            Record::Error(error) => {
+                self.errors.push(error);
+            }
+            Record::Checkpoint(checkpoint) => {
+                self.checkpoints.push(checkpoint);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.directories.len()
+            + self.files.len()
+            + self.symlinks.len()
+            + self.rollups.len()
+            + self.errors.len()
+            + self.checkpoints.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Ensure that the owners of a batch exist in the database, inserting those that do not in a
+/// single statement. Users are stored in a cache to prevent writing the same user multiple times.
+///
+/// Arguments
+/// * `owners` - The user ids to check.
+/// * `pool` - The database connection pool.
+/// * `cache` - The cache to store user ids.
+///
+/// Returns
+/// * Ok(()) if the users exist or are inserted successfully.
+/// * Err(sqlx::Error) if an error occurs while inserting the users.
+async fn ensure_users_exist(
+    owners: &HashSet<Option<i32>>,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cache: &mut HashSet<i32>,
+) -> Result<(), sqlx::Error> {
+    let missing: Vec<User> = owners
+        .iter()
+        .flatten()
+        .filter(|owner_id| !cache.contains(owner_id))
+        .map(|&owner_id| User {
+            user_id: owner_id,
+            username: users::username::get_username(owner_id as u32),
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    // Users already in the database keep their row
+    User::insert_many(pool, &missing).await?;
+    cache.extend(missing.iter().map(|user| user.user_id));
+    Ok(())
+}
+
+/// Ensure that the groups of a batch exist in the database, inserting those that do not in a
+/// single statement. Groups are stored in a cache to prevent writing the same group multiple times.
+///
+/// Arguments
+/// * `groups` - The group ids to check.
+/// * `pool` - The database connection pool.
+/// * `cache` - The cache to store group ids.
+///
+/// Returns
+/// * Ok(()) if the groups exist or are inserted successfully.
+/// * Err(sqlx::Error) if an error occurs while inserting the groups.
+async fn ensure_groups_exist(
+    groups: &HashSet<Option<i32>>,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cache: &mut HashSet<i32>,
+) -> Result<(), sqlx::Error> {
+    let missing: Vec<Group> = groups
+        .iter()
+        .flatten()
+        .filter(|group_id| !cache.contains(group_id))
+        .map(|&group_id| Group {
+            group_id,
+            groupname: users::groupname::get_groupname(group_id as u32),
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    // Groups already in the database keep their row
+    Group::insert_many(pool, &missing).await?;
+    cache.extend(missing.iter().map(|group| group.group_id));
+    Ok(())
+}
+
+/// Store the roll-up totals of a batch of finished directories, using a single `UNNEST` statement.
+///
+/// Returns the number of rows affected.
+async fn update_rollups(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    rollups: &[Rollup],
+) -> Result<u64, sqlx::Error> {
+    let column = |value: fn(&Rollup) -> i64| -> Vec<i64> { rollups.iter().map(value).collect() };
+    let directory_ids: Vec<String> = rollups.iter().map(|r| r.directory_id.clone()).collect();
+
+    let result = sqlx::query(
+        r#"
+        UPDATE directories d
+        SET direct_files = r.direct_files, direct_subdirectories = r.direct_subdirectories,
+            direct_bytes = r.direct_bytes, direct_allocated_bytes = r.direct_allocated_bytes,
+            total_files = r.total_files, total_subdirectories = r.total_subdirectories,
+            total_bytes = r.total_bytes, total_allocated_bytes = r.total_allocated_bytes
+        FROM UNNEST(
+            $1::TEXT[], $2::BIGINT[], $3::BIGINT[], $4::BIGINT[], $5::BIGINT[],
+            $6::BIGINT[], $7::BIGINT[], $8::BIGINT[], $9::BIGINT[]
+        ) AS r(directory_id, direct_files, direct_subdirectories, direct_bytes, direct_allocated_bytes,
+            total_files, total_subdirectories, total_bytes, total_allocated_bytes)
+        WHERE d.directory_id = r.directory_id
+        "#,
+    )
+    .bind(directory_ids)
+    .bind(column(|r| r.direct_files))
+    .bind(column(|r| r.direct_subdirectories))
+    .bind(column(|r| r.direct_bytes))
+    .bind(column(|r| r.direct_allocated_bytes))
+    .bind(column(|r| r.total_files))
+    .bind(column(|r| r.total_subdirectories))
+    .bind(column(|r| r.total_bytes))
+    .bind(column(|r| r.total_allocated_bytes))
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Insert a batch of crawl errors using a single multi-row `UNNEST` statement.
+///
+/// Returns the number of rows affected.
+async fn insert_errors(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    errors: &[ScanError],
+) -> Result<u64, sqlx::Error> {
+    let scan_ids: Vec<Option<i32>> = errors.iter().map(|e| e.scan_id).collect();
+    let paths: Vec<String> = errors.iter().map(|e| e.path.clone()).collect();
+    let error_kinds: Vec<String> = errors.iter().map(|e| e.error_kind.clone()).collect();
+    let errnos: Vec<Option<i32>> = errors.iter().map(|e| e.errno).collect();
+    let phases: Vec<String> = errors.iter().map(|e| e.phase.clone()).collect();
+    let messages: Vec<Option<String>> = errors.iter().map(|e| e.message.clone()).collect();
+    let occurred_at: Vec<chrono::NaiveDateTime> = errors.iter().map(|e| e.occurred_at).collect();
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO scan_errors (scan_id, path, error_kind, errno, phase, message, occurred_at)
+        SELECT * FROM UNNEST(
+            $1::INT[], $2::TEXT[], $3::TEXT[], $4::INT[], $5::TEXT[], $6::TEXT[], $7::TIMESTAMP[]
+        )
+        "#,
+    )
+    .bind(scan_ids)
+    .bind(paths)
+    .bind(error_kinds)
+    .bind(errnos)
+    .bind(phases)
+    .bind(messages)
+    .bind(occurred_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Record a batch of finished subtrees using a single multi-row `UNNEST` statement.
+///
+/// Returns the number of rows affected.
+async fn insert_checkpoints(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    checkpoints: &[Checkpoint],
+) -> Result<u64, sqlx::Error> {
+    let scan_ids: Vec<i32> = checkpoints.iter().map(|c| c.scan_id).collect();
+    let paths: Vec<String> = checkpoints.iter().map(|c| c.path.clone()).collect();
+    let completed_at: Vec<chrono::NaiveDateTime> =
+        checkpoints.iter().map(|c| c.completed_at).collect();
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO scan_checkpoints (scan_id, path, completed_at)
+        SELECT * FROM UNNEST($1::INT[], $2::TEXT[], $3::TIMESTAMP[])
+        ON CONFLICT (scan_id, path) DO UPDATE SET completed_at = EXCLUDED.completed_at
+        "#,
+    )
+    .bind(scan_ids)
+    .bind(paths)
+    .bind(completed_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// The outcome of writing one kind of record.
+struct Written {
+    /// The number of rows written.
+    count: u64,
+    /// The records that could not be written.
+    dead: Vec<ScanError>,
+}
+
+/// Write a set of records, first as a single batch and, if that fails, one at a time so that a
+/// single bad row does not take the rest of the batch down with it.
+/// Transient errors are retried with backoff according to the policy.
+///
+/// Arguments
+/// * `pool` - The database connection pool.
+/// * `policy` - The retry policy.
+/// * `what` - The kind of record, used in log messages.
+/// * `rows` - The records to write.
+/// * `upsert` - Writes all the records in a single statement.
+/// * `key` - Returns the scan id and primary key of a record, used to report failures.
+async fn write_rows<T, F, Fut>(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    policy: &retry::Policy,
+    what: &str,
+    rows: &[T],
+    upsert: F,
+    key: fn(&T) -> (Option<i32>, &str),
+) -> Written
+where
+    T: DbModel + Sync,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<u64, sqlx::Error>>,
+{
+    let mut written = Written {
+        count: 0,
+        dead: Vec::new(),
+    };
+    if rows.is_empty() {
+        return written;
+    }
+
+    match retry::with_backoff(policy, &format!("writing {}", what), upsert).await {
+        Ok(count) => {
+            written.count = count;
+            return written;
+        }
+        Err(e) => log::warn!(
+            "Failed to write a batch of {} {}, writing them one at a time: {}",
+            rows.len(),
+            what,
+            e
+        ),
+    }
+
+    for row in rows {
+        let (scan_id, id) = key(row);
+        match retry::with_backoff(policy, &format!("writing {}", id), || row.insert(pool)).await {
+            Ok(()) => written.count += 1,
+            Err(e) => {
+                log::error!("Giving up on {}: {}", id, e);
+                written.dead.push(ScanError::from_sqlx(scan_id, id, &e));
+            }
+        }
+    }
+    written
+}
+
+/// Record the rows that could not be written as missing from the totals of the directories
+/// above them, and of themselves for directories.
+///
+/// Arguments
+/// * `incomplete` - The directories with rows missing below them.
+/// * `dead` - The records that could not be written.
+fn mark_incomplete(incomplete: &mut HashSet<String>, dead: &[ScanError]) {
+    for error in dead {
+        for directory in std::path::Path::new(&error.path).ancestors() {
+            // Once a directory is known, so are all the directories above it
+            match directory.to_str() {
+                Some(directory) if incomplete.insert(directory.to_string()) => {}
+                _ => break,
+            }
+        }
+    }
+}
+
+fn directory_key(directory: &Directory) -> (Option<i32>, &str) {
+    (directory.scan_id, &directory.directory_id)
+}
+
+fn file_key(file: &File) -> (Option<i32>, &str) {
+    (file.scan_id, &file.file_id)
+}
+
+fn symlink_key(symlink: &Symlink) -> (Option<i32>, &str) {
+    (symlink.scan_id, &symlink.symlink_id)
+}
+
+/// Flush the buffered records to the database.
+/// Directories are written before files and symlinks, so that those in the same batch can reference them.
+/// The crawler queues every directory before its contents, so by the time a file or symlink is
+/// flushed its parent has already been written. Records that cannot be written are recorded as
+/// dead letters in `scan_errors`. Checkpoints are written last, once everything queued before
+/// them has been written. Totals are left out for directories with files or subdirectories
+/// that could not be written, in this batch or an earlier one, so they are never larger than
+/// what the rows add up to.
+async fn flush(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    batch: &mut Batch,
+    user_cache: &mut HashSet<i32>,
+    group_cache: &mut HashSet<i32>,
+    incomplete: &mut HashSet<String>,
+    policy: &retry::Policy,
+    stats: &mut Stats,
+) {
+    let directories: Vec<Directory> = batch.directories.drain().map(|(_, d)| d).collect();
+    let files: Vec<File> = batch.files.drain().map(|(_, f)| f).collect();
+    let symlinks: Vec<Symlink> = batch.symlinks.drain().map(|(_, l)| l).collect();
+    let mut rollups: Vec<Rollup> = batch.rollups.drain().map(|(_, r)| r).collect();
+    let mut errors: Vec<ScanError> = std::mem::take(&mut batch.errors);
+    let checkpoints: Vec<Checkpoint> = std::mem::take(&mut batch.checkpoints);
+
+    let owners: HashSet<Option<i32>> = directories
+        .iter()
+        .map(|d| d.owner_id)
+        .chain(files.iter().map(|f| f.owner_id))
+        .chain(symlinks.iter().map(|l| l.owner_id))
+        .collect();
+    if let Err(e) = ensure_users_exist(&owners, pool, user_cache).await {
+        log::error!("Failed to insert users: {:?}", e);
+    }
+
+    let groups: HashSet<Option<i32>> = directories
+        .iter()
+        .map(|d| d.group_id)
+        .chain(files.iter().map(|f| f.group_id))
+        .collect();
+    if let Err(e) = ensure_groups_exist(&groups, pool, group_cache).await {
+        log::error!("Failed to insert groups: {:?}", e);
+    }
+
+    let mut dead: Vec<ScanError> = Vec::new();
+
+    let written = write_rows(
+        pool,
+        policy,
+        "directories",
+        &directories,
+        || Directory::upsert_many(pool, &directories),
+        directory_key,
+    )
+    .await;
+    stats.directories += written.count;
+    mark_incomplete(incomplete, &written.dead);
+    dead.extend(written.dead);
+
+    let written = write_rows(
+        pool,
+        policy,
+        "symlinks",
+        &symlinks,
+        || Symlink::upsert_many(pool, &symlinks),
+        symlink_key,
+    )
+    .await;
+    stats.symlinks += written.count;
+    dead.extend(written.dead);
+
+    let written = write_rows(
+        pool,
+        policy,
+        "files",
+        &files,
+        || File::upsert_many(pool, &files),
+        file_key,
+    )
+    .await;
+    stats.files += written.count;
+    let failed: HashSet<&str> = written.dead.iter().map(|e| e.path.as_str()).collect();
+    stats.bytes += files
+        .iter()
+        .filter(|f| !failed.contains(f.file_id.as_str()))
+        .map(|f| f.size.max(0) as u64)
+        .sum::<u64>();
+    mark_incomplete(incomplete, &written.dead);
+    dead.extend(written.dead);
+
+    // Directories missing their totals are answered by the recursive query in `estimate`
+    let finished = rollups.len();
+    rollups.retain(|rollup| !incomplete.contains(&rollup.directory_id));
+    if rollups.len() < finished {
+        log::warn!(
+            "Leaving out the totals of {} directories with rows that could not be written",
+            finished - rollups.len()
+        );
+    }
+    if !rollups.is_empty() {
+        if let Err(e) = retry::with_backoff(policy, "writing directory totals", || {
+            update_rollups(pool, &rollups)
+        })
+        .await
+        {
+            log::error!("Error updating directory totals: {:?}", e);
+        }
+    }
+
+    errors.extend(dead.iter().cloned());
+    stats.dead_letters.extend(dead);
+
+    if !errors.is_empty() {
+        match retry::with_backoff(policy, "writing scan errors", || {
+            insert_errors(pool, &errors)
+        })
+        .await
+        {
+            Ok(count) => stats.errors += count,
+            Err(e) => log::error!("Error inserting scan errors: {:?}", e),
+        }
+    }
+
+    // A missing checkpoint only means the subtree is walked again when the scan is resumed
+    if !checkpoints.is_empty() {
+        match retry::with_backoff(policy, "writing checkpoints", || {
+            insert_checkpoints(pool, &checkpoints)
+        })
+        .await
+        {
+            Ok(count) => stats.checkpoints += count,
+            Err(e) => log::error!("Error inserting checkpoints: {:?}", e),
+        }
+    }
+
+    stats.batches += 1;
+}
+
+/// Spawn the writer task.
+/// The writer receives records from the crawler and flushes them in batches,
+/// either when the batch is full or when the flush interval elapses.
+///
+/// Arguments
+/// * `pool` - The database connection pool.
+/// * `receiver` - The receiving end of the record channel.
+/// * `config` - The batch size and flush interval.
+///
+/// Returns
+/// A handle to the writer task, resolving to the writer statistics once the channel is closed
+/// and all records have been flushed.
+pub fn spawn(
+    pool: std::sync::Arc<sqlx::Pool<sqlx::Postgres>>,
+    mut receiver: tokio::sync::mpsc::Receiver<Record>,
+    config: Config,
+) -> tokio::task::JoinHandle<Stats> {
+    tokio::spawn(async move {
+        let mut batch = Batch::default();
+        let mut user_cache: HashSet<i32> = HashSet::new();
+        let mut group_cache: HashSet<i32> = HashSet::new();
+        let mut incomplete: HashSet<String> = HashSet::new();
+        let mut stats = Stats::default();
+        let mut ticker = tokio::time::interval(config.flush_interval);
+
+        loop {
+            tokio::select! {
+                record = receiver.recv() => match record {
+                    Some(record) => {
+                        batch.push(record);
+                        if batch.len() >= config.batch_size {
+                            flush(
+                                &pool,
+                                &mut batch,
+                                &mut user_cache,
+                                &mut group_cache,
+                                &mut incomplete,
+                                &config.retry,
+                                &mut stats,
+                            )
+                            .await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    if !batch.is_empty() {
+                        flush(
+                            &pool,
+                            &mut batch,
+                            &mut user_cache,
+                            &mut group_cache,
+                            &mut incomplete,
+                            &config.retry,
+                            &mut stats,
+                        )
+                        .await;
+                    }
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            flush(
+                &pool,
+                &mut batch,
+                &mut user_cache,
+                &mut group_cache,
+                &mut incomplete,
+                &config.retry,
+                &mut stats,
+            )
+            .await;
+        }
+        stats
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dead_letter(path: &str) -> ScanError {
+        ScanError::from_sqlx(Some(1), path, &sqlx::Error::RowNotFound)
+    }
+
+    #[test]
+    fn dead_letters_mark_every_directory_above_them() {
+        let mut incomplete = HashSet::new();
+        mark_incomplete(&mut incomplete, &[dead_letter("/data/a/b/file")]);
+        mark_incomplete(&mut incomplete, &[dead_letter("/data/a/c")]);
+
+        for directory in ["/", "/data", "/data/a", "/data/a/b", "/data/a/c"] {
+            assert!(incomplete.contains(directory), "{}", directory);
+        }
+        assert!(!incomplete.contains("/data/d"));
+        assert!(!incomplete.contains("/data/a/b2"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+/// SQLSTATE raised when Postgres breaks a deadlock by aborting one of the transactions.
+const DEADLOCK_DETECTED: &str = "40P01";
+/// SQLSTATE raised when a transaction cannot be serialized with a concurrent one.
+const SERIALIZATION_FAILURE: &str = "40001";
+/// SQLSTATE raised when a row references a missing row, such as a file whose parent directory
+/// has not been written yet.
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// How a failed write should be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The same statement is likely to succeed if retried after a short wait, e.g. a deadlock,
+    /// or a missing parent directory that another write may still be adding.
+    Transient,
+    /// Retrying will not help, e.g. a value too long for its column.
+    Permanent,
+}
+
+/// Classify a database error.
+///
+/// Arguments
+/// * `error` - The error returned by the database.
+///
+/// Returns
+/// The class of the error, which decides whether the write is retried.
+pub fn classify(error: &sqlx::Error) -> ErrorClass {
+    match error {
+        sqlx::Error::Database(e) => match e.code().as_deref() {
+            Some(DEADLOCK_DETECTED) | Some(SERIALIZATION_FAILURE) | Some(FOREIGN_KEY_VIOLATION) => {
+                ErrorClass::Transient
+            }
+            _ => ErrorClass::Permanent,
+        },
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// Bounded exponential backoff.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each further retry doubles it.
+    pub base_delay: Duration,
+    /// The upper bound on a single delay.
+    pub max_delay: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Policy {
+    /// The delay before the given retry (1 for the first retry), with up to 50% random jitter
+    /// so that concurrent writers do not collide again in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = exponential.mul_f64(rand::random::<f64>() * 0.5);
+        exponential + jitter
+    }
+}
+
+/// Run a database operation, retrying it with backoff while it fails with a transient error.
+///
+/// Arguments
+/// * `policy` - The retry policy.
+/// * `what` - A description of the operation, used in log messages.
+/// * `operation` - The operation to run. It is called once per attempt.
+///
+/// Returns
+/// The result of the first successful attempt, or the last error once the error is not
+/// transient or the attempts are exhausted.
+pub async fn with_backoff<T, F, Fut>(
+    policy: &Policy,
+    what: &str,
+    mut operation: F,
+) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if classify(&e) == ErrorClass::Transient && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt);
+                log::warn!(
+                    "Transient error {} (attempt {}/{}), retrying in {:?}: {}",
+                    what,
+                    attempt,
+                    policy.max_attempts,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database error with a given SQLSTATE.
+    #[derive(Debug)]
+    struct StateError(&'static str);
+
+    impl std::fmt::Display for StateError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for StateError {}
+
+    impl sqlx::error::DatabaseError for StateError {
+        fn message(&self) -> &str {
+            "test error"
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    fn database_error(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(StateError(code)))
+    }
+
+    fn quick_policy(max_attempts: u32) -> Policy {
+        Policy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn deadlocks_serialization_failures_and_missing_parents_are_transient() {
+        assert_eq!(classify(&database_error("40P01")), ErrorClass::Transient);
+        assert_eq!(classify(&database_error("40001")), ErrorClass::Transient);
+        // foreign_key_violation
+        assert_eq!(classify(&database_error("23503")), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn constraint_and_data_errors_are_permanent() {
+        // string_data_right_truncation, unique_violation, not_null_violation
+        assert_eq!(classify(&database_error("22001")), ErrorClass::Permanent);
+        assert_eq!(classify(&database_error("23505")), ErrorClass::Permanent);
+        assert_eq!(classify(&database_error("23502")), ErrorClass::Permanent);
+        assert_eq!(classify(&sqlx::Error::RowNotFound), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn connection_errors_are_transient() {
+        assert_eq!(classify(&sqlx::Error::PoolTimedOut), ErrorClass::Transient);
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(classify(&sqlx::Error::Io(io)), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum_with_bounded_jitter() {
+        let policy = Policy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        for (retry, expected) in [(1, 100), (2, 200), (3, 400), (4, 500), (30, 500)] {
+            let expected = Duration::from_millis(expected);
+            let delay = policy.delay(retry);
+            assert!(
+                delay >= expected && delay <= expected.mul_f64(1.5),
+                "retry {}: {:?} not within 50% above {:?}",
+                retry,
+                delay,
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_until_success() {
+        let mut attempts = 0;
+        let result = with_backoff(&quick_policy(5), "testing", || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                if attempt < 3 {
+                    Err(sqlx::Error::PoolTimedOut)
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_the_last_attempt() {
+        let mut attempts = 0;
+        let result: Result<(), sqlx::Error> = with_backoff(&quick_policy(3), "testing", || {
+            attempts += 1;
+            async { Err(database_error("40P01")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_are_not_retried() {
+        let mut attempts = 0;
+        let result: Result<(), sqlx::Error> = with_backoff(&quick_policy(5), "testing", || {
+            attempts += 1;
+            async { Err(database_error("22001")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::users;
+use crate::writer::batch::Record;
+
+/// The number of owners listed in the summary.
+const TOP_OWNERS: usize = 10;
+
+/// The files owned by a single user.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OwnerUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Totals of a walk, collected instead of writing records to the database.
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub directories: u64,
+    pub files: u64,
+    pub symlinks: u64,
+    pub errors: u64,
+    pub bytes: u64,
+    pub allocated_bytes: u64,
+    pub owners: HashMap<Option<i32>, OwnerUsage>,
+}
+
+impl Summary {
+    fn add(&mut self, record: Record) {
+        match record {
+            Record::Directory(_) => self.directories += 1,
+            Record::File(file) => {
+                let size = file.size.max(0) as u64;
+                self.files += 1;
+                self.bytes += size;
+                self.allocated_bytes += file.allocated_size.unwrap_or_default().max(0) as u64;
+                let owner = self.owners.entry(file.owner_id).or_default();
+                owner.files += 1;
+                owner.bytes += size;
+            }
+            Record::Symlink(_) => self.symlinks += 1,
+            Record::Error(_) => self.errors += 1,
+            Record::Rollup(_) | Record::Checkpoint(_) => {}
+        }
+    }
+
+    /// Render the totals as a table.
+    pub fn totals_table(&self) -> comfy_table::Table {
+        let mut table = comfy_table::Table::new();
+        table.load_preset(comfy_table::presets::UTF8_FULL);
+        table.set_header(vec![
+            "directories",
+            "files",
+            "symlinks",
+            "errors",
+            "size",
+            "allocated_size",
+        ]);
+        table.add_row(vec![
+            self.directories.to_string(),
+            self.files.to_string(),
+            self.symlinks.to_string(),
+            self.errors.to_string(),
+            self.bytes.to_string(),
+            self.allocated_bytes.to_string(),
+        ]);
+        table
+    }
+
+    /// Render the owners of the most bytes as a table.
+    pub fn owners_table(&self) -> comfy_table::Table {
+        let mut owners: Vec<(&Option<i32>, &OwnerUsage)> = self.owners.iter().collect();
+        owners.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.bytes));
+
+        let mut table = comfy_table::Table::new();
+        table.load_preset(comfy_table::presets::UTF8_FULL);
+        table.set_header(vec!["owner", "owner_id", "files", "size"]);
+        for (owner_id, usage) in owners.into_iter().take(TOP_OWNERS) {
+            let username = owner_id
+                .and_then(|owner_id| users::username::get_username(owner_id as u32))
+                .unwrap_or_else(|| "unknown".to_string());
+            table.add_row(vec![
+                username,
+                owner_id.map(|id| id.to_string()).unwrap_or_default(),
+                usage.files.to_string(),
+                usage.bytes.to_string(),
+            ]);
+        }
+        table
+    }
+}
+
+/// Spawn a task that consumes records in place of the batch writer, without touching the database.
+///
+/// Arguments
+/// * `receiver` - The receiving end of the record channel.
+///
+/// Returns
+/// A handle to the task, resolving to the summary once the channel is closed.
+pub fn spawn(
+    mut receiver: tokio::sync::mpsc::Receiver<Record>,
+) -> tokio::task::JoinHandle<Summary> {
+    tokio::spawn(async move {
+        let mut summary = Summary::default();
+        while let Some(record) = receiver.recv().await {
+            summary.add(record);
+        }
+        summary
+    })
+}
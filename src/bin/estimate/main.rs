@@ -1,22 +1,257 @@
 use clap::Parser;
+use db::filter::Filter;
+use futures::TryStreamExt;
 use sqlx::types::BigDecimal;
 use sqlx::Row;
 
-use db;
-
 #[derive(clap::Parser, Default, Debug)]
 #[clap(author = "Dheshan Mohandass", version, about)]
 /// A companion tool for the disk usage tracker to estimate a directory's size.
 struct Arguments {
-    /// The path to the directory to estimate.
+    /// The path to the directory to estimate. Relative paths, `.`, `..` and trailing slashes are
+    /// normalized as the crawler does, and paths that are not valid UTF-8 are looked up exactly.
     #[clap(short, long)]
-    path: String,
+    path: std::path::PathBuf,
     /// The number of large files to display.
     #[clap(short, long, default_value = "5")]
     large_files_count: usize,
-    /// The offset to start displaying large files.
-    #[clap(short, long, default_value = "0")]
-    offset: usize,
+    /// List the large files following this one, as printed at the end of the previous listing.
+    #[clap(short, long)]
+    after: Option<String>,
+    /// Deprecated, use --after instead: skip this many of the largest files. Every skipped file
+    /// is read again on each page.
+    #[clap(short, long, conflicts_with = "after")]
+    offset: Option<usize>,
+    /// How to count files with several hard links.
+    #[clap(long, value_enum, default_value = "naive")]
+    hard_links: HardLinkMode,
+    /// Which size to report: the apparent size (like `du --apparent-size`) or the allocated size (like `du`).
+    #[clap(long, value_enum, default_value = "apparent")]
+    size: SizeKind,
+    /// Break the totals down per mount point.
+    #[clap(long)]
+    by_mount: bool,
+    /// Break the totals down per owning group.
+    #[clap(long)]
+    by_group: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+/// Which size of the files is reported.
+enum SizeKind {
+    /// The length of the files in bytes.
+    #[default]
+    Apparent,
+    /// The space allocated to the files on disk.
+    Allocated,
+    /// Report both sizes.
+    Both,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+/// How files with several hard links are counted in the totals.
+enum HardLinkMode {
+    /// Count every link, as if each were a separate file.
+    #[default]
+    Naive,
+    /// Count each inode once.
+    Unique,
+    /// Report both totals.
+    Both,
+}
+
+/// A row of the largest files listing.
+#[derive(sqlx::FromRow)]
+struct LargeFile {
+    file_id: String,
+    size_mb: i64,
+    owner_id: Option<i32>,
+    last_modified: Option<chrono::NaiveDateTime>,
+}
+
+/// Quote a value so it can be pasted into a POSIX shell command line as a single argument.
+/// Values made only of characters the shell does not interpret are left as they are.
+///
+/// * `value` - The value to quote.
+fn shell_quote(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "/._-+,:=@%".contains(c);
+    if !value.is_empty() && value.chars().all(plain) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Log a size in bytes, along with its KB, MB, GB and TB equivalents.
+///
+/// * `label` - A description of the size.
+/// * `total_size` - The size in bytes.
+fn log_size(label: &str, total_size: &BigDecimal) {
+    let size_kb: BigDecimal = total_size.clone() / 1024;
+    let size_mb: BigDecimal = size_kb.clone() / 1024;
+    let size_gb: BigDecimal = size_mb.clone() / 1024;
+    let size_tb: BigDecimal = size_gb.clone() / 1024;
+
+    log::info!(
+        "{}: {:.2} TB = {:.2} GB = {:.2} MB = {:.2} KB = {:.2} bytes",
+        label,
+        size_tb,
+        size_gb,
+        size_mb,
+        size_kb,
+        total_size
+    );
+}
+
+/// Start a query with a `directory_tree` table holding the directories matched by a filter and
+/// every directory below them.
+///
+/// * `columns` - The columns of `directories d` kept in the table.
+/// * `root` - The filter matching the directories at the top of the tree.
+///
+/// Returns the query, to be completed with a statement using `directory_tree`.
+fn with_directory_tree<'a>(columns: &str, root: &Filter) -> sqlx::QueryBuilder<'a, sqlx::Postgres> {
+    let mut query = sqlx::QueryBuilder::new(format!(
+        r#"
+        WITH RECURSIVE directory_tree AS (
+            SELECT {columns}
+            FROM directories d
+            WHERE "#
+    ));
+    root.push_to(&mut query);
+    query.push(format!(
+        r#"
+
+            UNION ALL
+
+            SELECT {columns}
+            FROM directories d
+            INNER JOIN directory_tree dt ON d.parent_id = dt.directory_id
+        )"#
+    ));
+    query
+}
+
+/// Format the age of a scan in a human-readable form.
+///
+/// * `age` - The time elapsed since the scan.
+///
+/// Returns a string such as `2d 3h 15m`.
+fn format_age(age: chrono::Duration) -> String {
+    let minutes = age.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / (24 * 60), (minutes / 60) % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// Check that a directory was recorded by a scan, and explain why it was not otherwise.
+///
+/// * `pool` - The database connection pool.
+/// * `path` - The id of the directory.
+///
+/// Returns None if the directory is in the database, or a description of why it is not.
+async fn missing_reason(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    path: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let directory = sqlx::query("SELECT 1 AS found FROM directories WHERE directory_id = $1")
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+    if directory.is_some() {
+        return Ok(None);
+    }
+
+    let file = sqlx::query("SELECT 1 AS found FROM files WHERE file_id = $1")
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+    if file.is_some() {
+        return Ok(Some(format!(
+            "{} is a file, not a directory: pass the directory containing it",
+            path
+        )));
+    }
+
+    // The latest scan whose root contains the path, compared as a prefix up to a `/`
+    let covering = sqlx::query(
+        r#"
+        SELECT scan_id, root, status
+        FROM scans
+        WHERE root = $1 OR root = '/' OR left($1, length(root) + 1) = root || '/'
+        ORDER BY scan_id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(path)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(scan) = covering {
+        let scan_id: i32 = scan.try_get("scan_id")?;
+        let root: String = scan.try_get("root")?;
+        let status: String = scan.try_get("status")?;
+        return Ok(Some(format!(
+            "{} is not in the database, although scan {} of {} ({}) covers it: it may have been excluded, unreadable, or created after the scan",
+            path, scan_id, root, status
+        )));
+    }
+
+    let roots: Vec<String> = sqlx::query("SELECT DISTINCT root FROM scans ORDER BY root")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.try_get("root"))
+        .collect::<Result<_, _>>()?;
+    if roots.is_empty() {
+        Ok(Some(format!(
+            "{} is not in the database: no scans have been recorded",
+            path
+        )))
+    } else {
+        Ok(Some(format!(
+            "{} is not in the database: no scan covers it (scanned roots: {})",
+            path,
+            roots.join(", ")
+        )))
+    }
+}
+
+/// Count the directories in a subtree that could not be read by the scans that recorded it.
+/// The scans are those of the directories in the subtree, so an unreadable root with no files
+/// under it is still reported.
+///
+/// * `pool` - The database connection pool.
+/// * `root` - The filter selecting the root of the subtree, on `d.directory_id`.
+/// * `path` - The id of the root directory.
+///
+/// Returns the number of unreadable directories, and the first of them.
+async fn unreadable_directories(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    root: &Filter,
+    path: &str,
+) -> Result<(i64, Option<String>), sqlx::Error> {
+    let mut query = with_directory_tree("d.directory_id, d.scan_id", root);
+    query.push(
+        r#"
+        SELECT COUNT(*) AS unreadable, MIN(e.path) AS example
+        FROM scan_errors e
+        WHERE e.phase = 'walk'
+        AND e.error_kind <> 'FilesystemLoop'
+        AND e.scan_id IN (SELECT scan_id FROM directory_tree)
+        AND "#,
+    );
+    Filter::eq("e.path", path)
+        .or(Filter::prefix(
+            "e.path",
+            format!("{}/", path.trim_end_matches('/')),
+        ))
+        .push_to(&mut query);
+    let row = query.build().fetch_one(pool).await?;
+    Ok((row.try_get("unreadable")?, row.try_get("example")?))
 }
 
 #[tokio::main]
@@ -28,9 +263,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Arguments::parse();
     log::info!("{:?}", args);
 
-    let path = args.path;
+    // Normalized and encoded the same way the crawler records its paths
+    let path = db::paths::to_id(&args.path)?;
+    log::info!("Estimating: {}", path);
     let large_files_count = args.large_files_count;
-    let offset = args.offset;
+    let hard_links = args.hard_links;
+    let size = args.size;
 
     // get the database url from the environment
     let database_url = match std::env::var("DATABASE_URL") {
@@ -56,105 +294,380 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let query = format!(
-        r#"
-        WITH RECURSIVE directory_tree AS (
-            SELECT d.directory_id
-            FROM directories d
-            WHERE d.directory_id = '{path}'
+    if let Some(reason) = missing_reason(&pool, &path).await? {
+        log::error!("{}", reason);
+        return Err(format!("Path not in database: {}", path).into());
+    }
+    let root = Filter::eq("d.directory_id", &path);
 
-            UNION ALL
-            
-            SELECT d.directory_id
-            FROM directories d
-            INNER JOIN directory_tree dt ON d.parent_id = dt.directory_id
-        )
-        SELECT COALESCE(SUM(f.size), 0) AS total_size
+    // The totals recorded by the crawler answer the naive totals with a single lookup.
+    // Counting hard links once needs the files themselves, so it always takes the recursive query.
+    let rollup = match hard_links {
+        HardLinkMode::Naive => {
+            sqlx::query(
+                r#"
+                SELECT total_bytes, total_allocated_bytes, total_files, scan_id
+                FROM directories
+                WHERE directory_id = $1 AND total_bytes IS NOT NULL
+                "#,
+            )
+            .bind(&path)
+            .fetch_optional(&pool)
+            .await?
+        }
+        _ => None,
+    };
+
+    let (total_size, allocated_size, unique_size, unique_allocated_size) = match rollup {
+        Some(rollup) => {
+            let total_bytes: i64 = rollup.try_get("total_bytes")?;
+            let total_allocated_bytes: i64 = rollup.try_get("total_allocated_bytes")?;
+            let total_files: i64 = rollup.try_get("total_files")?;
+            let scan_id: Option<i32> = rollup.try_get("scan_id")?;
+            log::info!(
+                "Using the totals recorded by scan {} ({} files)",
+                scan_id.map(|id| id.to_string()).unwrap_or_default(),
+                total_files
+            );
+            let total_size = BigDecimal::from(total_bytes);
+            let allocated_size = BigDecimal::from(total_allocated_bytes);
+            (
+                total_size.clone(),
+                allocated_size.clone(),
+                total_size,
+                allocated_size,
+            )
+        }
+        None => {
+            let mut query = with_directory_tree("d.directory_id", &root);
+            query.push(
+                r#",
+                tree_files AS (
+                    SELECT f.size, COALESCE(f.allocated_size, 0) AS allocated_size, f.device, f.inode, f.nlink
+                    FROM files f
+                    WHERE f.directory_id IN (SELECT directory_id FROM directory_tree)
+                ),
+                linked_files AS (
+                    SELECT DISTINCT ON (device, inode) size, allocated_size
+                    FROM tree_files
+                    WHERE nlink > 1
+                )
+                SELECT
+                    COALESCE((SELECT SUM(size) FROM tree_files), 0) AS total_size,
+                    COALESCE((SELECT SUM(allocated_size) FROM tree_files), 0) AS allocated_size,
+                    COALESCE((SELECT SUM(size) FROM tree_files WHERE nlink IS NULL OR nlink <= 1), 0)
+                    + COALESCE((SELECT SUM(size) FROM linked_files), 0) AS unique_size,
+                    COALESCE((SELECT SUM(allocated_size) FROM tree_files WHERE nlink IS NULL OR nlink <= 1), 0)
+                    + COALESCE((SELECT SUM(allocated_size) FROM linked_files), 0) AS unique_allocated_size
+                "#,
+            );
+
+            let result = query.build().fetch_one(&pool).await?;
+
+            // NUMERIC type
+            let total_size: BigDecimal = result.try_get("total_size")?;
+            let unique_size: BigDecimal = result.try_get("unique_size")?;
+            let allocated_size: BigDecimal = result.try_get("allocated_size")?;
+            let unique_allocated_size: BigDecimal = result.try_get("unique_allocated_size")?;
+
+            (
+                total_size,
+                allocated_size,
+                unique_size,
+                unique_allocated_size,
+            )
+        }
+    };
+
+    if size != SizeKind::Allocated {
+        if hard_links != HardLinkMode::Unique {
+            log_size("Estimated size", &total_size);
+        }
+        if hard_links != HardLinkMode::Naive {
+            log_size("Estimated size (hard links counted once)", &unique_size);
+        }
+    }
+    if size != SizeKind::Apparent {
+        if hard_links != HardLinkMode::Unique {
+            log_size("Estimated allocated size", &allocated_size);
+        }
+        if hard_links != HardLinkMode::Naive {
+            log_size(
+                "Estimated allocated size (hard links counted once)",
+                &unique_allocated_size,
+            );
+        }
+    }
+
+    if args.by_mount {
+        let mut mounts_query =
+            with_directory_tree("d.directory_id, d.mount_point, d.fs_type", &root);
+        mounts_query.push(
+            r#"
+            SELECT
+                COALESCE(dt.mount_point, 'unknown') AS mount_point,
+                COALESCE(dt.fs_type, 'unknown') AS fs_type,
+                COUNT(DISTINCT dt.directory_id) AS directories,
+                COUNT(f.file_id) AS files,
+                COALESCE(SUM(f.size), 0) AS total_size,
+                COALESCE(SUM(f.allocated_size), 0) AS allocated_size
+            FROM directory_tree dt
+            LEFT JOIN files f ON f.directory_id = dt.directory_id
+            GROUP BY 1, 2
+            ORDER BY total_size DESC
+            "#,
+        );
+
+        let mounts = mounts_query.build().fetch_all(&pool).await?;
+
+        let mut table = comfy_table::Table::new();
+        table.load_preset(comfy_table::presets::UTF8_FULL);
+        table.set_header(vec![
+            "mount_point",
+            "fs_type",
+            "directories",
+            "files",
+            "size",
+            "allocated_size",
+        ]);
+        for mount in mounts {
+            let mount_point: String = mount.try_get("mount_point")?;
+            let fs_type: String = mount.try_get("fs_type")?;
+            let directories: i64 = mount.try_get("directories")?;
+            let files: i64 = mount.try_get("files")?;
+            let total_size: BigDecimal = mount.try_get("total_size")?;
+            let allocated_size: BigDecimal = mount.try_get("allocated_size")?;
+            table.add_row(vec![
+                mount_point,
+                fs_type,
+                directories.to_string(),
+                files.to_string(),
+                total_size.to_string(),
+                allocated_size.to_string(),
+            ]);
+        }
+
+        println!("Usage per mount point:");
+        println!("{table}");
+    }
+
+    if args.by_group {
+        let mut groups_query = with_directory_tree("d.directory_id", &root);
+        groups_query.push(
+            r#"
+            SELECT
+                f.group_id,
+                COALESCE(g.groupname, 'unknown') AS groupname,
+                COUNT(f.file_id) AS files,
+                COALESCE(SUM(f.size), 0) AS total_size,
+                COALESCE(SUM(f.allocated_size), 0) AS allocated_size
+            FROM files f
+            LEFT JOIN groups g ON g.group_id = f.group_id
+            WHERE f.directory_id IN (SELECT directory_id FROM directory_tree)
+            GROUP BY f.group_id, g.groupname
+            ORDER BY total_size DESC
+            "#,
+        );
+
+        let groups = groups_query.build().fetch_all(&pool).await?;
+
+        let mut table = comfy_table::Table::new();
+        table.load_preset(comfy_table::presets::UTF8_FULL);
+        table.set_header(vec!["group", "group_id", "files", "size", "allocated_size"]);
+        for group in groups {
+            let group_id: Option<i32> = group.try_get("group_id")?;
+            let groupname: String = group.try_get("groupname")?;
+            let files: i64 = group.try_get("files")?;
+            let total_size: BigDecimal = group.try_get("total_size")?;
+            let allocated_size: BigDecimal = group.try_get("allocated_size")?;
+            table.add_row(vec![
+                groupname,
+                group_id.map(|id| id.to_string()).unwrap_or_default(),
+                files.to_string(),
+                total_size.to_string(),
+                allocated_size.to_string(),
+            ]);
+        }
+
+        println!("Usage per group:");
+        println!("{table}");
+    }
+
+    let mut scans_query = with_directory_tree("d.directory_id", &root);
+    scans_query.push(
+        r#"
+        SELECT s.scan_id, s.root, s.host, s.status, s.started_at, s.finished_at, COUNT(*) AS file_count
         FROM files f
+        INNER JOIN scans s ON s.scan_id = f.scan_id
         WHERE f.directory_id IN (SELECT directory_id FROM directory_tree)
-        "#
+        GROUP BY s.scan_id
+        ORDER BY s.scan_id DESC
+        "#,
     );
 
-    let result = sqlx::query(&query).fetch_one(&pool).await?;
+    let scans = scans_query.build().fetch_all(&pool).await?;
+    if scans.is_empty() {
+        log::warn!("No scan information found for: {}", path);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    for scan in scans {
+        let scan_id: i32 = scan.try_get("scan_id")?;
+        let root: String = scan.try_get("root")?;
+        let host: Option<String> = scan.try_get("host")?;
+        let status: String = scan.try_get("status")?;
+        let started_at: chrono::NaiveDateTime = scan.try_get("started_at")?;
+        let finished_at: Option<chrono::NaiveDateTime> = scan.try_get("finished_at")?;
+        let file_count: i64 = scan.try_get("file_count")?;
 
-    // NUMERIC type
-    let total_size: BigDecimal = result.try_get("total_size")?;
-    let size_kb: BigDecimal = total_size.clone() / 1024;
-    let size_mb: BigDecimal = size_kb.clone() / 1024;
-    let size_gb: BigDecimal = size_mb.clone() / 1024;
-    let size_tb: BigDecimal = size_gb.clone() / 1024;
+        let as_of = finished_at.unwrap_or(started_at);
+        log::info!(
+            "Data from scan {} of {} on {} ({}): {} files, as of {} ({} old)",
+            scan_id,
+            root,
+            host.as_deref().unwrap_or("unknown host"),
+            status,
+            file_count,
+            as_of,
+            format_age(now - as_of)
+        );
+        if status != "completed" {
+            log::warn!("Scan {} did not complete, totals may be partial", scan_id);
+        }
+    }
 
-    log::info!(
-        "Estimated size: {:.2} TB = {:.2} GB = {:.2} MB = {:.2} KB = {:.2} bytes",
-        size_tb,
-        size_gb,
-        size_mb,
-        size_kb,
-        total_size
-    );
+    let (unreadable_count, example) = unreadable_directories(&pool, &root, &path).await?;
+    if unreadable_count > 0 {
+        log::warn!(
+            "{} directories under {} could not be read during the scan (e.g. {}), totals are an undercount",
+            unreadable_count,
+            path,
+            example.unwrap_or_default()
+        );
+    }
 
-    let largest_files_query = format!(
-        r#"
-        WITH RECURSIVE directory_tree AS (
-            SELECT d.directory_id
-            FROM directories d
-            WHERE d.directory_id = '{path}'
+    // Later pages seek past the last file listed instead of skipping rows with OFFSET
+    let mut page = db::keyset::Page::new("f.size", "f.file_id", large_files_count);
+    if let Some(after) = &args.after {
+        let size: Option<i64> = sqlx::query_scalar("SELECT size FROM files WHERE file_id = $1")
+            .bind(after)
+            .fetch_optional(&pool)
+            .await?;
+        let Some(size) = size else {
+            log::error!("{} is not a file in the database", after);
+            return Err(format!("File not in database: {}", after).into());
+        };
+        page = page.after(db::keyset::Cursor {
+            key: size.into(),
+            id: after.clone(),
+        });
+    }
 
-            UNION ALL
-            
-            SELECT d.directory_id
-            FROM directories d
-            INNER JOIN directory_tree dt ON d.parent_id = dt.directory_id
-        )
+    // Bound like every other value, which connectorx cannot do, so the rows are read with sqlx
+    let mut largest_files_query = with_directory_tree("d.directory_id", &root);
+    largest_files_query.push(
+        r#"
         SELECT f.file_id, f.size / 1024 / 1024 AS size_mb, f.owner_id, f.last_modified
         FROM files f
         WHERE f.directory_id IN (SELECT directory_id FROM directory_tree)
-        ORDER BY f.size DESC
-        OFFSET {offset}
-        LIMIT {large_files_count}
-        "#,
+        AND "#,
     );
+    page.push_condition(&mut largest_files_query);
+    page.push_order(&mut largest_files_query);
+    if let Some(offset) = args.offset {
+        log::warn!("--offset is deprecated and will be removed, list the next files with --after");
+        largest_files_query
+            .push(" OFFSET ")
+            .push_bind(offset as i64);
+    }
+    // Streamed, so a long listing is printed without first reading every row into memory
+    let mut largest_files = db::stream::fetch::<LargeFile>(&pool, largest_files_query);
+    let (mut listed, mut more_files, mut last_file_id) = (0, false, None);
 
-    let _ = tokio::task::spawn_blocking(move || {
-        let dfs = db::execute_queries::returning_df(&database_url, vec![&largest_files_query]);
-        if let Err(e) = dfs {
-            log::error!("Failed to return DataFrame: {}", e);
-            return;
+    let mut table = comfy_table::Table::new();
+    table.load_preset(comfy_table::presets::UTF8_FULL);
+    table.set_header(vec!["file_id", "size_mb", "owner_id", "last_modified"]);
+    while let Some(file) = largest_files.try_next().await? {
+        // The page fetches one row more than it holds, to tell whether another page follows
+        if listed == large_files_count {
+            more_files = true;
+            break;
         }
+        listed += 1;
+        last_file_id = Some(file.file_id.clone());
+        table.add_row(vec![
+            file.file_id,
+            file.size_mb.to_string(),
+            file.owner_id.map(|id| id.to_string()).unwrap_or_default(),
+            file.last_modified
+                .map(|time| time.to_string())
+                .unwrap_or_default(),
+        ]);
+    }
 
-        let dfs = dfs.unwrap();
-        let df = &dfs[0];
+    println!("Largest files in directory:");
+    println!("{table}");
+    if let (true, Some(last_file_id)) = (more_files, last_file_id) {
+        log::info!(
+            "List the next files with: --after {}",
+            shell_quote(&last_file_id)
+        );
+    }
 
-        // get col names
-        let mut col_names: Vec<&str> = vec![];
-        for series in df.get_columns() {
-            col_names.push(series.name());
-        }
+    Ok(())
+}
 
-        let mut table = comfy_table::Table::new();
-        table.load_preset(comfy_table::presets::UTF8_FULL);
-        table.set_header(&col_names);
-
-        // iterate over the rows
-        let df_row_count = df.height();
-        for i in 0..df_row_count {
-            let mut row: Vec<String> = vec![];
-            for series in df.get_columns() {
-                let value: Result<polars::prelude::AnyValue, polars::prelude::PolarsError> =
-                    series.get(i);
-                let value_str = match value {
-                    Ok(value) => value.to_string(),
-                    Err(_) => "".to_string(),
-                };
-                row.push(value_str);
-            }
-            table.add_row(row);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        println!("Largest files in directory:");
-        println!("{table}")
-    })
-    .await?;
+    /// A scan whose root could not be read has a directory row for the root, an error for it,
+    /// and no files at all.
+    #[tokio::test]
+    async fn unreadable_root_is_reported() {
+        let Ok(database_url) = std::env::var("DATABASE_URL") else {
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&database_url).await.unwrap();
+        let path = format!("/nonexistent-estimate-test-{}", std::process::id());
 
-    Ok(())
+        let scan_id: i32 = sqlx::query_scalar(
+            "INSERT INTO scans (root, started_at, status) VALUES ($1, NOW(), 'completed') RETURNING scan_id",
+        )
+        .bind(&path)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO directories (directory_id, scan_id, raw_path) VALUES ($1, $2, $3)",
+        )
+        .bind(&path)
+        .bind(scan_id)
+        .bind(path.as_bytes())
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO scan_errors (scan_id, path, error_kind, phase, occurred_at)
+            VALUES ($1, $2, 'PermissionDenied', 'walk', NOW())
+            "#,
+        )
+        .bind(scan_id)
+        .bind(&path)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let unreadable =
+            unreadable_directories(&pool, &Filter::eq("d.directory_id", &path), &path).await;
+
+        for table in ["scan_errors", "directories", "scans"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE scan_id = $1"))
+                .bind(scan_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        assert_eq!(unreadable.unwrap(), (1, Some(path)));
+    }
 }
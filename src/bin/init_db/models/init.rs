@@ -19,7 +19,36 @@ pub async fn drop_all(pool: &sqlx::PgPool, debug: bool) -> Result<(), sqlx::Erro
         DROP TABLE IF EXISTS files;
     "#;
 
-    let drop_queries = vec![drop_file_table, drop_directory_table, drop_user_table];
+    let drop_symlink_table = r#"
+        DROP TABLE IF EXISTS symlinks;
+    "#;
+
+    let drop_scan_error_table = r#"
+        DROP TABLE IF EXISTS scan_errors;
+    "#;
+
+    let drop_scan_checkpoint_table = r#"
+        DROP TABLE IF EXISTS scan_checkpoints;
+    "#;
+
+    let drop_group_table = r#"
+        DROP TABLE IF EXISTS groups;
+    "#;
+
+    let drop_scan_table = r#"
+        DROP TABLE IF EXISTS scans;
+    "#;
+
+    let drop_queries = vec![
+        drop_file_table,
+        drop_symlink_table,
+        drop_directory_table,
+        drop_scan_error_table,
+        drop_scan_checkpoint_table,
+        drop_scan_table,
+        drop_user_table,
+        drop_group_table,
+    ];
 
     db::execute_queries::as_transaction(pool, drop_queries, debug).await?;
 
@@ -40,12 +69,52 @@ pub async fn initialize(pool: &sqlx::PgPool, debug: bool) -> Result<(), sqlx::Er
         );
     "#;
 
+    let create_group_table = r#"
+        CREATE TABLE groups (
+            group_id INT PRIMARY KEY,
+            groupname TEXT
+        );
+    "#;
+
+    let create_scan_table = r#"
+        CREATE TABLE scans (
+            scan_id SERIAL PRIMARY KEY,
+            root TEXT NOT NULL,
+            host TEXT,
+            started_at TIMESTAMP NOT NULL,
+            finished_at TIMESTAMP,
+            status TEXT NOT NULL,
+            file_count BIGINT NOT NULL DEFAULT 0,
+            directory_count BIGINT NOT NULL DEFAULT 0,
+            total_bytes BIGINT NOT NULL DEFAULT 0,
+            exclude_rules TEXT[] NOT NULL DEFAULT '{}'
+        );
+    "#;
+
     let create_directory_table = r#"
         CREATE TABLE directories (
             directory_id TEXT PRIMARY KEY,
             owner_id INT,
             parent_id TEXT,
-            FOREIGN KEY (owner_id) REFERENCES users(user_id)
+            mount_point TEXT,
+            fs_type TEXT,
+            group_id INT,
+            mode INT,
+            scan_id INT,
+            -- Paths are stored as raw bytes, and in the id columns as UTF-8 with other bytes escaped
+            raw_path BYTEA NOT NULL,
+            -- Totals computed by the crawler once the subtree is walked, NULL until then
+            direct_files BIGINT,
+            direct_subdirectories BIGINT,
+            direct_bytes BIGINT,
+            direct_allocated_bytes BIGINT,
+            total_files BIGINT,
+            total_subdirectories BIGINT,
+            total_bytes BIGINT,
+            total_allocated_bytes BIGINT,
+            FOREIGN KEY (owner_id) REFERENCES users(user_id),
+            FOREIGN KEY (group_id) REFERENCES groups(group_id),
+            FOREIGN KEY (scan_id) REFERENCES scans(scan_id)
         );
     "#;
 
@@ -60,13 +129,28 @@ pub async fn initialize(pool: &sqlx::PgPool, debug: bool) -> Result<(), sqlx::Er
     let create_file_table = r#"
         CREATE TABLE files (
             file_id TEXT PRIMARY KEY,
-            name VARCHAR(255) NOT NULL,
+            -- Escaped names may be longer than the 255 bytes a name is limited to
+            name TEXT NOT NULL,
             size BIGINT NOT NULL,
+            allocated_size BIGINT,
             owner_id INT,
+            group_id INT,
+            mode INT,
             directory_id TEXT NOT NULL,
             last_modified TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            device BIGINT,
+            inode BIGINT,
+            nlink BIGINT,
+            atime_ns BIGINT,
+            mtime_ns BIGINT,
+            ctime_ns BIGINT,
+            btime_ns BIGINT,
+            scan_id INT,
+            raw_path BYTEA NOT NULL,
             FOREIGN KEY (owner_id) REFERENCES users(user_id),
-            FOREIGN KEY (directory_id) REFERENCES directories(directory_id)
+            FOREIGN KEY (group_id) REFERENCES groups(group_id),
+            FOREIGN KEY (directory_id) REFERENCES directories(directory_id),
+            FOREIGN KEY (scan_id) REFERENCES scans(scan_id)
         );
     "#;
 
@@ -74,13 +158,87 @@ pub async fn initialize(pool: &sqlx::PgPool, debug: bool) -> Result<(), sqlx::Er
         CREATE INDEX file_directory_id ON files(directory_id);
     "#;
 
+    let create_symlink_table = r#"
+        CREATE TABLE symlinks (
+            symlink_id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            target TEXT NOT NULL,
+            dangling BOOLEAN NOT NULL,
+            size BIGINT NOT NULL,
+            owner_id INT,
+            directory_id TEXT NOT NULL,
+            scan_id INT,
+            raw_path BYTEA NOT NULL,
+            raw_target BYTEA NOT NULL,
+            FOREIGN KEY (owner_id) REFERENCES users(user_id),
+            FOREIGN KEY (directory_id) REFERENCES directories(directory_id),
+            FOREIGN KEY (scan_id) REFERENCES scans(scan_id)
+        );
+    "#;
+
+    let create_symlink_directory_index = r#"
+        CREATE INDEX symlink_directory_id ON symlinks(directory_id text_pattern_ops);
+    "#;
+
+    let create_scan_error_table = r#"
+        CREATE TABLE scan_errors (
+            error_id BIGSERIAL PRIMARY KEY,
+            scan_id INT,
+            path TEXT NOT NULL,
+            error_kind TEXT NOT NULL,
+            errno INT,
+            phase TEXT NOT NULL,
+            message TEXT,
+            occurred_at TIMESTAMP NOT NULL,
+            FOREIGN KEY (scan_id) REFERENCES scans(scan_id)
+        );
+    "#;
+
+    let create_scan_error_path_index = r#"
+        CREATE INDEX scan_error_path ON scan_errors(scan_id, path text_pattern_ops);
+    "#;
+
+    // The top-level subtrees a scan has finished, so that an interrupted scan can be resumed
+    let create_scan_checkpoint_table = r#"
+        CREATE TABLE scan_checkpoints (
+            scan_id INT NOT NULL,
+            path TEXT NOT NULL,
+            completed_at TIMESTAMP NOT NULL,
+            PRIMARY KEY (scan_id, path),
+            FOREIGN KEY (scan_id) REFERENCES scans(scan_id)
+        );
+    "#;
+
+    let create_file_inode_index = r#"
+        CREATE INDEX file_inode ON files(device, inode);
+    "#;
+
+    // Prefix indexes, used to find the rows under a scanned root
+    let create_directory_prefix_index = r#"
+        CREATE INDEX directory_id_prefix ON directories(directory_id text_pattern_ops);
+    "#;
+
+    let create_file_directory_prefix_index = r#"
+        CREATE INDEX file_directory_id_prefix ON files(directory_id text_pattern_ops);
+    "#;
+
     let init_queries = vec![
         create_user_table,
+        create_group_table,
+        create_scan_table,
         create_directory_table,
         create_directory_owner_index,
         create_directory_parent_index,
         create_file_table,
         create_file_directory_index,
+        create_symlink_table,
+        create_symlink_directory_index,
+        create_scan_error_table,
+        create_scan_error_path_index,
+        create_scan_checkpoint_table,
+        create_file_inode_index,
+        create_directory_prefix_index,
+        create_file_directory_prefix_index,
     ];
 
     db::execute_queries::as_transaction(pool, init_queries, debug).await?;
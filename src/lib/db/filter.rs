@@ -0,0 +1,401 @@
+use std::ops::{Bound, RangeBounds};
+
+/// A value compared against a column. Values are always sent as bind parameters, never
+/// written into the SQL text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    BigInt(i64),
+    Bool(bool),
+    Text(String),
+    Bytes(Vec<u8>),
+    Timestamp(chrono::NaiveDateTime),
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::BigInt(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Self {
+        Value::Text(value.clone())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Value::Bytes(value)
+    }
+}
+
+impl From<chrono::NaiveDateTime> for Value {
+    fn from(value: chrono::NaiveDateTime) -> Self {
+        Value::Timestamp(value)
+    }
+}
+
+/// The destination of the SQL text and bind parameters of a filter: a query being built, or,
+/// in tests, a recorder.
+pub(crate) trait Sink {
+    /// Append SQL text.
+    fn push_sql(&mut self, sql: &str);
+    /// Append a value as a bind parameter.
+    fn push_value(&mut self, value: Value);
+}
+
+impl Sink for sqlx::QueryBuilder<'_, sqlx::Postgres> {
+    fn push_sql(&mut self, sql: &str) {
+        self.push(sql);
+    }
+
+    fn push_value(&mut self, value: Value) {
+        match value {
+            Value::Int(value) => self.push_bind(value),
+            Value::BigInt(value) => self.push_bind(value),
+            Value::Bool(value) => self.push_bind(value),
+            Value::Text(value) => self.push_bind(value),
+            Value::Bytes(value) => self.push_bind(value),
+            Value::Timestamp(value) => self.push_bind(value),
+        };
+    }
+}
+
+/// Records what is written to it, numbering bind parameters as Postgres does.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    pub sql: String,
+    pub values: Vec<Value>,
+}
+
+#[cfg(test)]
+impl Sink for Recorder {
+    fn push_sql(&mut self, sql: &str) {
+        self.sql.push_str(sql);
+    }
+
+    fn push_value(&mut self, value: Value) {
+        self.values.push(value);
+        self.sql.push_str(&format!("${}", self.values.len()));
+    }
+}
+
+/// A condition on the rows of a table, such as the `WHERE` clause of `DbModel::select_where`.
+///
+/// Columns are static strings chosen by the caller's code; every value is bound as a parameter,
+/// so paths and names containing quotes or wildcards are matched literally.
+///
+/// ```ignore
+/// let filter = Filter::eq("owner_id", 1000).and(Filter::range("size", 1024_i64..));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Matches every row.
+    All,
+    /// The column equals the value.
+    Eq(&'static str, Value),
+    /// The column starts with the text.
+    Prefix(&'static str, String),
+    /// The column lies within the bounds.
+    Range(&'static str, Bound<Value>, Bound<Value>),
+    /// The column equals one of the values.
+    In(&'static str, Vec<Value>),
+    /// Every filter matches.
+    And(Vec<Filter>),
+    /// At least one filter matches.
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    /// Match rows where a column equals a value.
+    ///
+    /// * `column` - The column to compare.
+    /// * `value` - The value it must equal.
+    pub fn eq(column: &'static str, value: impl Into<Value>) -> Filter {
+        Filter::Eq(column, value.into())
+    }
+
+    /// Match rows where a text column starts with a prefix. `%`, `_` and `\` in the prefix are
+    /// matched literally.
+    ///
+    /// * `column` - The column to compare.
+    /// * `prefix` - The text it must start with.
+    pub fn prefix(column: &'static str, prefix: impl Into<String>) -> Filter {
+        Filter::Prefix(column, prefix.into())
+    }
+
+    /// Match rows where a column lies within a range, such as `1024_i64..` or `start..end`.
+    ///
+    /// * `column` - The column to compare.
+    /// * `range` - The bounds the column must lie within.
+    pub fn range<T: Into<Value> + Clone>(
+        column: &'static str,
+        range: impl RangeBounds<T>,
+    ) -> Filter {
+        let bound = |bound: Bound<&T>| match bound {
+            Bound::Included(value) => Bound::Included(value.clone().into()),
+            Bound::Excluded(value) => Bound::Excluded(value.clone().into()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Filter::Range(column, bound(range.start_bound()), bound(range.end_bound()))
+    }
+
+    /// Match rows where a column equals one of several values. An empty list matches nothing.
+    ///
+    /// * `column` - The column to compare.
+    /// * `values` - The values it may equal.
+    pub fn is_in<T: Into<Value>>(
+        column: &'static str,
+        values: impl IntoIterator<Item = T>,
+    ) -> Filter {
+        Filter::In(column, values.into_iter().map(Into::into).collect())
+    }
+
+    /// Match rows matched by both filters.
+    pub fn and(self, other: Filter) -> Filter {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    /// Match rows matched by either filter.
+    pub fn or(self, other: Filter) -> Filter {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    /// Append the filter to a query as SQL, with its values as bind parameters.
+    ///
+    /// * `query` - The query, typically ending in `WHERE `.
+    pub fn push_to(&self, query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>) {
+        self.write(query);
+    }
+
+    /// Write the filter as SQL, with its values as bind parameters.
+    pub(crate) fn write(&self, sink: &mut impl Sink) {
+        match self {
+            Filter::All => sink.push_sql("TRUE"),
+            Filter::Eq(column, value) => {
+                sink.push_sql(column);
+                sink.push_sql(" = ");
+                sink.push_value(value.clone());
+            }
+            Filter::Prefix(column, prefix) => {
+                sink.push_sql(column);
+                sink.push_sql(" LIKE ");
+                sink.push_value(Value::Text(format!("{}%", escape_like(prefix))));
+            }
+            Filter::Range(column, start, end) => {
+                let mut conditions = Vec::new();
+                match start {
+                    Bound::Included(value) => conditions.push((" >= ", value)),
+                    Bound::Excluded(value) => conditions.push((" > ", value)),
+                    Bound::Unbounded => {}
+                }
+                match end {
+                    Bound::Included(value) => conditions.push((" <= ", value)),
+                    Bound::Excluded(value) => conditions.push((" < ", value)),
+                    Bound::Unbounded => {}
+                }
+                if conditions.is_empty() {
+                    sink.push_sql("TRUE");
+                    return;
+                }
+                sink.push_sql("(");
+                for (i, (operator, value)) in conditions.into_iter().enumerate() {
+                    if i > 0 {
+                        sink.push_sql(" AND ");
+                    }
+                    sink.push_sql(column);
+                    sink.push_sql(operator);
+                    sink.push_value(value.clone());
+                }
+                sink.push_sql(")");
+            }
+            Filter::In(column, values) => {
+                if values.is_empty() {
+                    sink.push_sql("FALSE");
+                    return;
+                }
+                sink.push_sql(column);
+                sink.push_sql(" IN (");
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        sink.push_sql(", ");
+                    }
+                    sink.push_value(value.clone());
+                }
+                sink.push_sql(")");
+            }
+            Filter::And(filters) => write_joined(sink, filters, " AND ", "TRUE"),
+            Filter::Or(filters) => write_joined(sink, filters, " OR ", "FALSE"),
+        }
+    }
+}
+
+/// Write filters joined by an operator, in parentheses, or `empty` if there are none.
+fn write_joined(sink: &mut impl Sink, filters: &[Filter], operator: &str, empty: &str) {
+    if filters.is_empty() {
+        sink.push_sql(empty);
+        return;
+    }
+    sink.push_sql("(");
+    for (i, filter) in filters.iter().enumerate() {
+        if i > 0 {
+            sink.push_sql(operator);
+        }
+        filter.write(sink);
+    }
+    sink.push_sql(")");
+}
+
+/// Escape the `LIKE` wildcards in a string, so it can be used as a literal prefix.
+pub fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(filter: &Filter) -> Recorder {
+        let mut recorder = Recorder::default();
+        filter.write(&mut recorder);
+        recorder
+    }
+
+    #[test]
+    fn eq_binds_the_value() {
+        let recorded = record(&Filter::eq("owner_id", 1000));
+        assert_eq!(recorded.sql, "owner_id = $1");
+        assert_eq!(recorded.values, vec![Value::Int(1000)]);
+    }
+
+    #[test]
+    fn prefix_escapes_wildcards() {
+        let recorded = record(&Filter::prefix("file_id", "/data/100%_x\\y"));
+        assert_eq!(recorded.sql, "file_id LIKE $1");
+        assert_eq!(
+            recorded.values,
+            vec![Value::Text("/data/100\\%\\_x\\\\y%".to_string())]
+        );
+    }
+
+    #[test]
+    fn range_binds_bounds_in_order() {
+        let recorded = record(&Filter::range("size", 10_i64..20));
+        assert_eq!(recorded.sql, "(size >= $1 AND size < $2)");
+        assert_eq!(recorded.values, vec![Value::BigInt(10), Value::BigInt(20)]);
+
+        let recorded = record(&Filter::range("size", ..=5_i64));
+        assert_eq!(recorded.sql, "(size <= $1)");
+        assert_eq!(recorded.values, vec![Value::BigInt(5)]);
+
+        let recorded = record(&Filter::range::<i64>("size", ..));
+        assert_eq!(recorded.sql, "TRUE");
+        assert!(recorded.values.is_empty());
+    }
+
+    #[test]
+    fn is_in_lists_every_value() {
+        let recorded = record(&Filter::is_in("scan_id", [1, 2, 3]));
+        assert_eq!(recorded.sql, "scan_id IN ($1, $2, $3)");
+        assert_eq!(
+            recorded.values,
+            vec![Value::Int(1), Value::Int(2), Value::Int(3)]
+        );
+        assert_eq!(record(&Filter::is_in::<i32>("scan_id", [])).sql, "FALSE");
+    }
+
+    #[test]
+    fn and_or_nest_with_values_in_order() {
+        let filter = Filter::eq("a", 1)
+            .and(Filter::eq("b", "x").or(Filter::prefix("c", "y")))
+            .and(Filter::eq("d", true));
+        let recorded = record(&filter);
+        assert_eq!(
+            recorded.sql,
+            "(a = $1 AND (b = $2 OR c LIKE $3) AND d = $4)"
+        );
+        assert_eq!(
+            recorded.values,
+            vec![
+                Value::Int(1),
+                Value::Text("x".to_string()),
+                Value::Text("y%".to_string()),
+                Value::Bool(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_and_or_match_all_or_nothing() {
+        assert_eq!(record(&Filter::And(vec![])).sql, "TRUE");
+        assert_eq!(record(&Filter::Or(vec![])).sql, "FALSE");
+        assert_eq!(record(&Filter::All).sql, "TRUE");
+    }
+
+    #[test]
+    fn values_never_reach_the_sql_text() {
+        let recorded = record(&Filter::eq("name", "x' OR '1'='1"));
+        assert_eq!(recorded.sql, "name = $1");
+    }
+
+    #[test]
+    fn escape_like_escapes_backslash_first() {
+        assert_eq!(escape_like("a%b_c\\d"), "a\\%b\\_c\\\\d");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+
+    #[test]
+    fn push_to_numbers_parameters_after_the_query() {
+        let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new("SELECT 1 WHERE x = ");
+        query.push_bind(0);
+        query.push(" AND ");
+        Filter::eq("y", 1)
+            .and(Filter::prefix("z", "a_"))
+            .push_to(&mut query);
+        assert_eq!(
+            query.sql(),
+            "SELECT 1 WHERE x = $1 AND (y = $2 AND z LIKE $3)"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::filter::{Sink, Value};
+
+/// The position a page starts after: the sort key and id of the last row of the previous page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub key: Value,
+    pub id: String,
+}
+
+/// A page of rows ordered by a key, largest first, with ties broken by a unique id.
+///
+/// Pages are read by seeking past the last row of the previous page instead of with `OFFSET`,
+/// so a page deep into a large table costs no more than the first one, and rows written between
+/// two pages do not shift the listing.
+#[derive(Debug, Clone)]
+pub struct Page {
+    key_column: &'static str,
+    id_column: &'static str,
+    size: usize,
+    after: Option<Cursor>,
+}
+
+impl Page {
+    /// The first page of rows.
+    ///
+    /// * `key_column` - The column the rows are ordered by.
+    /// * `id_column` - A unique column breaking ties between rows with the same key.
+    /// * `size` - The number of rows in a page.
+    pub fn new(key_column: &'static str, id_column: &'static str, size: usize) -> Page {
+        Page {
+            key_column,
+            id_column,
+            size,
+            after: None,
+        }
+    }
+
+    /// The page starting after a row.
+    ///
+    /// * `cursor` - The key and id of the last row of the previous page.
+    pub fn after(mut self, cursor: Cursor) -> Page {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Append the condition selecting the rows after the cursor, `TRUE` on the first page.
+    ///
+    /// * `query` - The query, in its `WHERE` clause.
+    pub fn push_condition(&self, query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>) {
+        self.write_condition(query);
+    }
+
+    /// Append the `ORDER BY` and `LIMIT` clauses of the page. One row more than the page holds
+    /// is fetched, to tell whether another page follows.
+    ///
+    /// * `query` - The query, after its `WHERE` clause.
+    pub fn push_order(&self, query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>) {
+        self.write_order(query);
+    }
+
+    fn write_condition(&self, sink: &mut impl Sink) {
+        let Some(cursor) = &self.after else {
+            sink.push_sql("TRUE");
+            return;
+        };
+        sink.push_sql("(");
+        sink.push_sql(self.key_column);
+        sink.push_sql(", ");
+        sink.push_sql(self.id_column);
+        sink.push_sql(") < (");
+        sink.push_value(cursor.key.clone());
+        sink.push_sql(", ");
+        sink.push_value(Value::Text(cursor.id.clone()));
+        sink.push_sql(")");
+    }
+
+    fn write_order(&self, sink: &mut impl Sink) {
+        sink.push_sql(" ORDER BY ");
+        sink.push_sql(self.key_column);
+        sink.push_sql(" DESC, ");
+        sink.push_sql(self.id_column);
+        sink.push_sql(" DESC LIMIT ");
+        sink.push_value(Value::BigInt(self.size as i64 + 1));
+    }
+
+    /// Split the rows fetched for the page into the page itself and whether another page follows.
+    ///
+    /// * `rows` - The rows returned by the query, in order.
+    pub fn split<T>(&self, mut rows: Vec<T>) -> (Vec<T>, bool) {
+        let more = rows.len() > self.size;
+        rows.truncate(self.size);
+        (rows, more)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Recorder;
+
+    #[test]
+    fn first_page_has_no_condition() {
+        let page = Page::new("f.size", "f.file_id", 5);
+        let mut recorded = Recorder::default();
+        page.write_condition(&mut recorded);
+        page.write_order(&mut recorded);
+        assert_eq!(
+            recorded.sql,
+            "TRUE ORDER BY f.size DESC, f.file_id DESC LIMIT $1"
+        );
+        assert_eq!(recorded.values, vec![Value::BigInt(6)]);
+    }
+
+    #[test]
+    fn later_pages_seek_past_the_cursor() {
+        let page = Page::new("f.size", "f.file_id", 5).after(Cursor {
+            key: Value::BigInt(1024),
+            id: "/data/it's".to_string(),
+        });
+        let mut recorded = Recorder::default();
+        page.write_condition(&mut recorded);
+        page.write_order(&mut recorded);
+        assert_eq!(
+            recorded.sql,
+            "(f.size, f.file_id) < ($1, $2) ORDER BY f.size DESC, f.file_id DESC LIMIT $3"
+        );
+        assert_eq!(
+            recorded.values,
+            vec![
+                Value::BigInt(1024),
+                Value::Text("/data/it's".to_string()),
+                Value::BigInt(6),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_detects_the_extra_row() {
+        let page = Page::new("size", "id", 3);
+        assert_eq!(page.split(vec![1, 2, 3, 4]), (vec![1, 2, 3], true));
+        assert_eq!(page.split(vec![1, 2, 3]), (vec![1, 2, 3], false));
+        assert_eq!(page.split(vec![1]), (vec![1], false));
+        assert_eq!(page.split(Vec::<i32>::new()), (vec![], false));
+    }
+}
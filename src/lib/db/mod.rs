@@ -1 +1,5 @@
 pub mod execute_queries;
+pub mod filter;
+pub mod keyset;
+pub mod paths;
+pub mod stream;
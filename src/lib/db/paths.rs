@@ -0,0 +1,180 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+
+/// Normalize a path given by a user, such as a crawl root or a path to estimate, so that the
+/// same directory always gets the same id.
+///
+/// Relative paths are resolved against the current directory, and `.`, `..`, repeated and
+/// trailing slashes are removed. This is done lexically, without resolving symbolic links, so
+/// a path means the same whether or not it exists on the machine doing the lookup.
+///
+/// * `path` - The path to normalize.
+///
+/// Returns the absolute, normalized path, or an error if the current directory is needed but
+/// cannot be read.
+pub fn normalize(path: &Path) -> std::io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => normalized.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    Ok(normalized)
+}
+
+/// The id of a path given by a user: the path normalized by `normalize`, then encoded by
+/// `encode`. Paths found by walking a normalized root are normalized already, and only need
+/// to be encoded.
+///
+/// * `path` - The path given by the user.
+///
+/// Returns the id of the path in the database.
+pub fn to_id(path: &Path) -> std::io::Result<String> {
+    Ok(encode(&normalize(path)?))
+}
+
+/// Encode a path as the text id stored for it in the database.
+///
+/// Valid UTF-8 is kept as is, so ordinary paths read naturally and still match `LIKE` prefixes.
+/// Bytes that are not valid UTF-8 are written as `\xHH` and a literal backslash as `\\`, so that
+/// distinct paths always get distinct ids. `decode` reverses the encoding.
+///
+/// * `path` - The path to encode.
+///
+/// Returns the encoded path.
+pub fn encode(path: &Path) -> String {
+    let bytes = path.as_os_str().as_bytes();
+    let mut encoded = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for character in chunk.valid().chars() {
+            if character == '\\' {
+                encoded.push_str("\\\\");
+            } else {
+                encoded.push(character);
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decode a path id produced by `encode`.
+///
+/// * `id` - The encoded path.
+///
+/// Returns the original path, or None if the id is not a valid encoding.
+pub fn decode(id: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(id.len());
+    let mut rest = id.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = match (byte, tail) {
+            (b'\\', [b'\\', tail @ ..]) => {
+                bytes.push(b'\\');
+                tail
+            }
+            (b'\\', [b'x', high, low, tail @ ..]) => {
+                let hex = std::str::from_utf8(&[*high, *low]).ok()?.to_string();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                tail
+            }
+            (b'\\', _) => return None,
+            (byte, tail) => {
+                bytes.push(byte);
+                tail
+            }
+        };
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// The raw bytes of a path, stored next to its encoded id so that it can be recovered exactly.
+///
+/// * `path` - The path.
+///
+/// Returns the bytes of the path.
+pub fn raw(path: &Path) -> Vec<u8> {
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Paths whose ids could collide if backslashes and invalid bytes were not both escaped.
+    fn tricky_paths() -> Vec<PathBuf> {
+        [
+            &b"/data/back\\slash"[..],
+            b"/data/\\x41",
+            b"/data/bad\xff",
+            b"/data/bad\\xff",
+            b"/data/bad\\\xff",
+        ]
+        .iter()
+        .map(|bytes| PathBuf::from(OsString::from_vec(bytes.to_vec())))
+        .collect()
+    }
+
+    #[test]
+    fn encode_keeps_valid_utf8() {
+        assert_eq!(encode(Path::new("/data/café/a b")), "/data/café/a b");
+    }
+
+    #[test]
+    fn encode_escapes_backslashes_and_invalid_bytes() {
+        assert_eq!(
+            encode(Path::new("/data/back\\slash")),
+            "/data/back\\\\slash"
+        );
+        let invalid = PathBuf::from(OsString::from_vec(b"/data/bad\xff".to_vec()));
+        assert_eq!(encode(&invalid), "/data/bad\\xff");
+    }
+
+    #[test]
+    fn decode_reverses_encode() {
+        for path in tricky_paths() {
+            assert_eq!(decode(&encode(&path)), Some(path));
+        }
+    }
+
+    #[test]
+    fn encoded_paths_are_distinct() {
+        let ids: Vec<String> = tricky_paths().iter().map(|path| encode(path)).collect();
+        let unique: std::collections::HashSet<&String> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len(), "{:?}", ids);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_escapes() {
+        assert_eq!(decode("/data/\\q"), None);
+        assert_eq!(decode("/data/\\xzz"), None);
+        assert_eq!(decode("/data/\\"), None);
+    }
+
+    #[test]
+    fn normalize_removes_dots_and_trailing_slashes() {
+        assert_eq!(
+            normalize(Path::new("/data//a/./b/../c/")).unwrap(),
+            PathBuf::from("/data/a/c")
+        );
+        assert_eq!(normalize(Path::new("/..")).unwrap(), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn normalize_resolves_relative_paths() {
+        let current = std::env::current_dir().unwrap();
+        assert_eq!(normalize(Path::new("a/..")).unwrap(), current);
+    }
+}
@@ -0,0 +1,40 @@
+use futures::stream::BoxStream;
+use futures::StreamExt;
+
+/// The number of rows a stream reads ahead of its consumer.
+pub const BUFFER: usize = 1024;
+
+/// Stream the rows of a query built at runtime, such as one ending in a `Filter`, reading them
+/// from the database as they are consumed, so whole tables can be read in constant memory.
+///
+/// The rows are fetched by a background task, at most `BUFFER` rows ahead of the consumer,
+/// since the query has to outlive the caller's borrow of it. It must be called from within a
+/// Tokio runtime. Dropping the stream stops the task.
+///
+/// * `pool` - The database connection pool.
+/// * `query` - The query returning the rows.
+///
+/// Returns the stream of rows, which ends after the first error.
+pub fn fetch<T>(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    mut query: sqlx::QueryBuilder<'static, sqlx::Postgres>,
+) -> BoxStream<'static, Result<T, sqlx::Error>>
+where
+    T: for<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> + Send + Unpin + 'static,
+{
+    let pool = pool.clone();
+    let (sender, receiver) = tokio::sync::mpsc::channel(BUFFER);
+    tokio::spawn(async move {
+        let mut rows = query.build_query_as::<T>().fetch(&pool);
+        while let Some(row) = rows.next().await {
+            let failed = row.is_err();
+            if sender.send(row).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|row| (row, receiver))
+    })
+    .boxed()
+}